@@ -0,0 +1,124 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{GrayImage, Luma};
+use qrcode::QrCode;
+
+use qrcam::decode::convert_uyvy;
+use qrcam::qr::{barcode_reader, decode_image, DecodeOptions};
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+
+/// Build a UYVY frame with a QR code drawn into the luma channel, so neither bench needs a camera.
+fn synthetic_uyvy() -> (u32, Vec<u8>) {
+    let code = QrCode::new(b"https://github.com/samuelcolvin/qrcam")
+        .unwrap()
+        .render::<Luma<u8>>()
+        .min_dimensions(400, 400)
+        .build();
+    let x_offset = (WIDTH - code.width()) / 2;
+    let y_offset = (HEIGHT - code.height()) / 2;
+
+    let stride = WIDTH * 2;
+    let mut data = vec![0u8; (stride * HEIGHT) as usize];
+    for row in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let luma = match (x.checked_sub(x_offset), row.checked_sub(y_offset)) {
+                (Some(cx), Some(cy)) if cx < code.width() && cy < code.height() => code.get_pixel(cx, cy)[0],
+                // a soft gradient background rather than a flat colour
+                _ => 128 + ((x + row) % 64) as u8,
+            };
+            let idx = (row * stride + x * 2) as usize;
+            // chroma at the neutral point, luma in the odd bytes
+            data[idx] = 128;
+            data[idx + 1] = luma;
+        }
+    }
+    (stride, data)
+}
+
+/// A grey frame with a grid of distinct small QR codes, for comparing decode thread counts.
+fn synthetic_multi_code() -> GrayImage {
+    let mut frame = GrayImage::from_pixel(WIDTH, HEIGHT, Luma([255]));
+    for row in 0..3i64 {
+        for column in 0..6i64 {
+            let code = QrCode::new(format!("code {}-{}", row, column))
+                .unwrap()
+                .render::<Luma<u8>>()
+                .min_dimensions(200, 200)
+                .build();
+            image::imageops::replace(&mut frame, &code, column * 310 + 20, row * 350 + 40);
+        }
+    }
+    frame
+}
+
+/// Codes printed faintly, 30 levels apart, under a bright glare across the frame, the kind of frame
+/// contrast normalization is for. The glare varies per code so some are harder than others.
+fn synthetic_low_contrast() -> Vec<GrayImage> {
+    (0..8u32)
+        .map(|i| {
+            let code = QrCode::new(format!("low contrast {}", i))
+                .unwrap()
+                .render::<Luma<u8>>()
+                .min_dimensions(300, 300)
+                .build();
+            let mut frame = GrayImage::new(640, 480);
+            for (x, y, pixel) in frame.enumerate_pixels_mut() {
+                let glare = (x + y) * (4 + i) / 64;
+                let luma = match (x.checked_sub(170), y.checked_sub(90)) {
+                    (Some(cx), Some(cy)) if cx < code.width() && cy < code.height() => code.get_pixel(cx, cy)[0],
+                    _ => 255,
+                };
+                let luma = if luma == 0 { 110 } else { 140 };
+                *pixel = Luma([(luma + glare).min(255) as u8]);
+            }
+            frame
+        })
+        .collect()
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let (stride, data) = synthetic_uyvy();
+
+    c.bench_function("convert_uyvy", |b| {
+        b.iter(|| convert_uyvy(black_box(stride), black_box(HEIGHT), black_box(&data), false))
+    });
+
+    let (_, grey_img): (_, GrayImage) = convert_uyvy(stride, HEIGHT, &data, false);
+    let reader = barcode_reader(&DecodeOptions::default());
+    c.bench_function("zxingcpp_decode", |b| {
+        b.iter(|| reader.from(black_box(&grey_img)).unwrap())
+    });
+}
+
+fn bench_decode_threads(c: &mut Criterion) {
+    let grey_img = synthetic_multi_code();
+    for decode_threads in [1, 2, 4] {
+        let options = DecodeOptions {
+            max_results: 32,
+            decode_threads,
+            ..DecodeOptions::default()
+        };
+        let reader = barcode_reader(&options);
+        c.bench_function(&format!("decode_18_codes_{}_threads", decode_threads), |b| {
+            b.iter(|| decode_image(&reader, black_box(&grey_img), &options).unwrap())
+        });
+    }
+}
+
+fn bench_normalize_contrast(c: &mut Criterion) {
+    let frames = synthetic_low_contrast();
+    for normalize_contrast in [false, true] {
+        let options = DecodeOptions {
+            normalize_contrast,
+            ..DecodeOptions::default()
+        };
+        let reader = barcode_reader(&options);
+        c.bench_function(&format!("decode_low_contrast_normalized_{}", normalize_contrast), |b| {
+            b.iter(|| decode_image(&reader, black_box(&frames[0]), &options).unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, bench_decode, bench_decode_threads, bench_normalize_contrast);
+criterion_main!(benches);
@@ -0,0 +1,41 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Dependencies whose versions `qrcam --version` reports, as locked in `Cargo.lock`.
+const REPORTED: [&str; 4] = ["zxing-cpp", "image", "gpui", "av-foundation"];
+
+fn main() {
+    let lock_path = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock_path.display());
+    let lock = fs::read_to_string(&lock_path).unwrap_or_default();
+    let versions: Vec<String> = REPORTED
+        .iter()
+        .map(|name| locked_version(&lock, name).unwrap_or_else(|| format!("{} unknown", name)))
+        .collect();
+    // rustc-env values can't contain newlines, they're split on ';' where used
+    println!("cargo:rustc-env=QRCAM_DEPENDENCY_VERSIONS={}", versions.join(";"));
+}
+
+/// `name version`, with the commit for git dependencies, of a package in the lock file.
+fn locked_version(lock: &str, name: &str) -> Option<String> {
+    let package = lock
+        .split("[[package]]")
+        .find(|package| field(package, "name") == Some(name))?;
+    let version = field(package, "version")?;
+    // only git sources have a commit after a '#'
+    Some(
+        match field(package, "source").and_then(|source| source.split_once('#')) {
+            Some((_, commit)) => format!("{} {} ({})", name, version, &commit[..commit.len().min(8)]),
+            None => format!("{} {}", name, version),
+        },
+    )
+}
+
+/// The value of a `key = "value"` line in a lock file package.
+fn field<'a>(package: &'a str, key: &str) -> Option<&'a str> {
+    package.lines().find_map(|line| {
+        let (line_key, value) = line.split_once(" = ")?;
+        (line_key.trim() == key).then(|| value.trim().trim_matches('"'))
+    })
+}
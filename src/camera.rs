@@ -1,3 +1,9 @@
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
 use av_foundation::capture_device::AVCaptureDeviceTypeExternalUnknown;
 use av_foundation::{
     capture_device::{
@@ -10,28 +16,68 @@ use av_foundation::{
     capture_video_data_output::{AVCaptureVideoDataOutput, AVCaptureVideoDataOutputSampleBufferDelegate},
     media_format::AVMediaTypeVideo,
 };
+use block2::Block;
 use core_foundation::base::TCFType;
+use core_media::format_description::{
+    CMFormatDescriptionRef, CMVideoDimensions, CMVideoFormatDescriptionGetDimensions,
+};
 use core_media::sample_buffer::{CMSampleBuffer, CMSampleBufferRef};
-use core_video::pixel_buffer::CVPixelBuffer;
+use core_media::time::CMTime;
+use core_video::pixel_buffer::{kCVPixelBufferPixelFormatTypeKey, CVPixelBuffer};
 use dispatch2::{Queue, QueueAttribute};
+use image::RgbaImage;
 use objc2::{
-    declare_class, extern_methods, msg_send_id, mutability,
+    class, declare_class, extern_methods, msg_send, msg_send_id, mutability,
     rc::{Allocated, Id},
-    runtime::ProtocolObject,
-    ClassType, DeclaredClass,
+    runtime::{AnyClass, AnyObject, ProtocolObject, Sel},
+    sel, ClassType, DeclaredClass,
+};
+use objc2_foundation::{
+    NSArray, NSData, NSDictionary, NSError, NSInteger, NSMutableArray, NSNumber, NSObject, NSObjectProtocol, NSString,
+    NSURL,
 };
-use objc2_foundation::{NSMutableArray, NSObject, NSObjectProtocol, NSString};
+use serde::{Deserialize, Serialize};
 use x_media::media_frame::MediaFrame;
 
-use crate::decode::Decoder;
+use crate::decode::{lock, Decoder};
+use crate::qr::{Backend, CodeFormat, DecodeOptions, Detection};
 
 #[derive(Clone, Debug)]
 pub struct DeviceInfo {
     id: String,
+    /// The device's name, with a ` #N` suffix when several devices share a name, see `find_all`.
     pub name: String,
+    /// The name the device reports, the same for identical devices, e.g. two of the same capture card.
+    pub localized_name: String,
+    /// Where known, to tell apart devices with the same name, e.g. identical USB capture cards.
+    pub manufacturer: Option<String>,
+    pub model_id: Option<String>,
+    /// Which way the camera faces, `None` for cameras that don't say, e.g. USB webcams.
+    pub position: Option<Facing>,
+}
+
+/// Which way a camera faces, from `AVCaptureDevice.position`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Facing {
+    Front,
+    Back,
+}
+
+impl Facing {
+    fn from_av_position(position: NSInteger) -> Option<Self> {
+        // AVCaptureDevicePositionBack and AVCaptureDevicePositionFront
+        match position {
+            1 => Some(Self::Back),
+            2 => Some(Self::Front),
+            _ => None,
+        }
+    }
 }
 
 impl DeviceInfo {
+    /// Every camera, with names made unique: devices sharing a name are numbered ` #1`, ` #2`... in order
+    /// of their unique ID, which for USB devices follows the port they're plugged into.
     pub fn find_all() -> Vec<Self> {
         let mut device_types = NSMutableArray::new();
         let session = unsafe {
@@ -44,28 +90,340 @@ impl DeviceInfo {
                 AVCaptureDevicePositionUnspecified,
             )
         };
-        session
+        let mut devices: Vec<Self> = session
             .devices()
             .iter()
-            .map(|device| DeviceInfo {
-                id: device.unique_id().to_string(),
-                name: device.localized_name().to_string(),
+            .map(|device| {
+                let manufacturer: Option<Id<NSString>> = unsafe { msg_send_id![device, manufacturer] };
+                let model_id: Option<Id<NSString>> = unsafe { msg_send_id![device, modelID] };
+                let position: NSInteger = unsafe { msg_send![device, position] };
+                let name = device.localized_name().to_string();
+                DeviceInfo {
+                    id: device.unique_id().to_string(),
+                    name: name.clone(),
+                    localized_name: name,
+                    manufacturer: non_empty(manufacturer),
+                    model_id: non_empty(model_id),
+                    position: Facing::from_av_position(position),
+                }
             })
-            .collect()
+            .collect();
+        disambiguate_names(&mut devices);
+        devices
+    }
+
+    /// The camera to use: the first facing `facing` if given, otherwise the first back camera,
+    /// falling back to the first camera found.
+    pub fn find_preferred(facing: Option<Facing>) -> Option<Self> {
+        let devices = Self::find_all();
+        let position = facing.unwrap_or(Facing::Back);
+        let preferred = devices.iter().position(|device| device.position == Some(position));
+        match (preferred, facing) {
+            (Some(index), _) => devices.into_iter().nth(index),
+            (None, Some(_)) => None,
+            (None, None) => devices.into_iter().next(),
+        }
+    }
+
+    /// The camera with this ID, or failing that with this name, ignoring case. A name shared by several
+    /// devices picks the first, with a warning to use one of their numbered names instead.
+    pub fn find(query: &str) -> Option<Self> {
+        let devices = Self::find_all();
+        let index = devices.iter().position(|device| device.id == query).or_else(|| {
+            devices
+                .iter()
+                .position(|device| device.name.eq_ignore_ascii_case(query))
+        });
+        let index = index.or_else(|| {
+            let matching: Vec<usize> = (0..devices.len())
+                .filter(|&index| devices[index].localized_name.eq_ignore_ascii_case(query))
+                .collect();
+            if matching.len() > 1 {
+                let names: Vec<&str> = matching.iter().map(|&index| devices[index].name.as_str()).collect();
+                log::warn!("{:?} matches {}, using the first", query, names.join(", "));
+            }
+            matching.first().copied()
+        })?;
+        devices.into_iter().nth(index)
+    }
+
+    /// AVFoundation's unique ID for the device, stable across reconnects.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        match (&self.manufacturer, &self.model_id) {
+            (Some(manufacturer), Some(model_id)) => write!(f, " ({} {})", manufacturer, model_id),
+            (Some(detail), None) | (None, Some(detail)) => write!(f, " ({})", detail),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Number devices that share a name, in order of their IDs so each keeps its number between runs.
+fn disambiguate_names(devices: &mut [DeviceInfo]) {
+    let mut order: Vec<usize> = (0..devices.len()).collect();
+    order.sort_by(|&a, &b| devices[a].id.cmp(&devices[b].id));
+    for &index in &order {
+        let name = devices[index].localized_name.clone();
+        let duplicates = devices.iter().filter(|device| device.localized_name == name).count();
+        if duplicates > 1 {
+            let number = order
+                .iter()
+                .take_while(|&&other| other != index)
+                .filter(|&&other| devices[other].localized_name == name)
+                .count()
+                + 1;
+            devices[index].name = format!("{} #{}", name, number);
+        }
+    }
+}
+
+fn non_empty(string: Option<Id<NSString>>) -> Option<String> {
+    string
+        .map(|string| string.to_string())
+        .filter(|string| !string.is_empty())
+}
+
+/// How frames are delivered from the capture session to the decoder.
+///
+/// The defaults favour latency: frames arriving while the previous one is still being processed are
+/// dropped, so the preview and decoder always see the newest frame. Keeping late frames and/or using
+/// a concurrent queue means fewer frames are missed, at the cost of a growing backlog and lag when
+/// frame processing can't keep up with the camera.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureOptions {
+    /// Label of the dispatch queue sample buffers are delivered on, shown in debuggers and profilers.
+    pub queue_label: String,
+    /// Deliver frames on a concurrent queue so several can be converted at once.
+    pub concurrent_queue: bool,
+    /// Drop frames that arrive while the previous frame is still being handled.
+    pub discard_late_frames: bool,
+    /// How many times to retry starting the camera, e.g. when it isn't ready straight after wake.
+    pub start_retries: u32,
+    /// Delay before the first retry in milliseconds, doubling for each subsequent retry.
+    pub start_retry_delay_ms: u64,
+    /// Only use a camera facing this way, by default a back camera is preferred when there is one.
+    pub facing: Option<Facing>,
+    /// Run the camera at this many frames per second, clamped to what its format supports, e.g. lower
+    /// to reduce heat and CPU on a long-running kiosk. The camera's default if unset.
+    pub frame_rate: Option<f64>,
+    /// Ask the camera for frames in this pixel format, by default the first of `PixelFormat::PREFERRED`
+    /// the camera supports. `bgra` skips the YUV conversion, so is worth trying on cameras whose frames
+    /// come out garbled.
+    pub pixel_format: Option<PixelFormat>,
+    /// Experimental: flash the torch on and off while the window is open, only decoding frames taken
+    /// with it off, for glossy or laminated codes the torch's glare hides. Only cameras with a torch,
+    /// like an iPhone used as a Continuity Camera, are affected.
+    pub torch_pulse: bool,
+}
+
+impl CaptureOptions {
+    /// Delay before retry number `attempt` (starting at 0) with exponential backoff.
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.start_retry_delay_ms.saturating_mul(1 << attempt.min(16)))
+    }
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            queue_label: "com.video-capture.output".to_string(),
+            concurrent_queue: false,
+            discard_late_frames: true,
+            start_retries: 4,
+            start_retry_delay_ms: 250,
+            facing: None,
+            frame_rate: None,
+            pixel_format: None,
+            torch_pulse: false,
+        }
+    }
+}
+
+/// A pixel format frames can be delivered in that the decoder understands, see
+/// `DeviceCapture::set_pixel_format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PixelFormat {
+    /// Bi-planar 4:2:0 with video range luma, `420v`.
+    Planar420VideoRange,
+    /// Bi-planar 4:2:0 with full range luma, `420f`.
+    Planar420FullRange,
+    /// Packed 4:2:2, `2vuy`.
+    Uyvy,
+    /// Packed 32 bit BGRA, `BGRA`.
+    Bgra,
+}
+
+impl PixelFormat {
+    /// In order of preference: luma is a plane of its own in the planar formats so is cheapest to extract.
+    pub const PREFERRED: [Self; 4] = [
+        Self::Planar420VideoRange,
+        Self::Planar420FullRange,
+        Self::Uyvy,
+        Self::Bgra,
+    ];
+
+    /// The `OSType` Core Video identifies the format by.
+    pub fn code(self) -> u32 {
+        u32::from_be_bytes(match self {
+            Self::Planar420VideoRange => *b"420v",
+            Self::Planar420FullRange => *b"420f",
+            Self::Uyvy => *b"2vuy",
+            Self::Bgra => *b"BGRA",
+        })
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::PREFERRED.into_iter().find(|format| format.code() == code)
+    }
+
+    /// The first of `PREFERRED` in `available`, a list of Core Video format codes.
+    pub fn preferred(available: &[u32]) -> Option<Self> {
+        Self::PREFERRED
+            .into_iter()
+            .find(|format| available.contains(&format.code()))
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&fourcc(self.code()))
+    }
+}
+
+/// Show a Core Video format code as its four characters, e.g. `420v`, or as hex if they aren't printable.
+pub fn fourcc(code: u32) -> String {
+    let bytes = code.to_be_bytes();
+    if bytes.iter().all(|byte| byte.is_ascii_graphic() || *byte == b' ') {
+        bytes.iter().map(|&byte| byte as char).collect()
+    } else {
+        format!("{:#010x}", code)
+    }
+}
+
+/// How the camera focuses, see `DeviceCapture::set_focus`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FocusMode {
+    /// Continuous autofocus, the default.
+    #[default]
+    Auto,
+    /// Lock focus at the lens's current position.
+    Locked,
+    /// Lock focus at a lens position between 0.0 (closest) and 1.0 (furthest).
+    Manual(f32),
+}
+
+/// One of the capture formats a device supports, see `DeviceCapture::formats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureFormat {
+    /// Position in the device's list of formats, used to select it with `DeviceCapture::set_format`.
+    pub index: usize,
+    pub width: i32,
+    pub height: i32,
+    pub frame_rate_ranges: Vec<FrameRateRange>,
+}
+
+impl CaptureFormat {
+    pub fn supports_frame_rate(&self, fps: f64) -> bool {
+        self.frame_rate_ranges
+            .iter()
+            .any(|range| (range.min..=range.max).contains(&fps))
+    }
+
+    pub fn max_frame_rate(&self) -> Option<f64> {
+        self.frame_rate_ranges.iter().map(|range| range.max).reduce(f64::max)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameRateRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// The camera's current exposure settings, see `DeviceCapture::exposure`. Each is `None` where the
+/// device or OS doesn't report it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Exposure {
+    /// Exposure time of each frame in seconds.
+    pub duration: Option<f64>,
+    pub iso: Option<f32>,
+    /// Focus position between 0.0 (closest) and 1.0 (furthest).
+    pub lens_position: Option<f32>,
+}
+
+impl fmt::Display for Exposure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let duration = match self.duration {
+            Some(duration) if duration > 0. => format!("1/{:.0}s", 1. / duration),
+            _ => "-".to_string(),
+        };
+        let iso = self.iso.map_or("-".to_string(), |iso| format!("{:.0}", iso));
+        let lens_position = self
+            .lens_position
+            .map_or("-".to_string(), |lens_position| format!("{:.2}", lens_position));
+        write!(f, "exposure {}, ISO {}, lens {}", duration, iso, lens_position)
     }
 }
 
+// values of AVCaptureFocusMode
+const AV_CAPTURE_FOCUS_MODE_LOCKED: NSInteger = 0;
+const AV_CAPTURE_FOCUS_MODE_CONTINUOUS_AUTO_FOCUS: NSInteger = 2;
+
+// values of AVCaptureTorchMode
+const AV_CAPTURE_TORCH_MODE_OFF: NSInteger = 0;
+const AV_CAPTURE_TORCH_MODE_ON: NSInteger = 1;
+
 pub struct DeviceCapture {
     session: Id<AVCaptureSession>,
+    device: Id<AVCaptureDevice>,
     input: Id<AVCaptureDeviceInput>,
     output: Id<AVCaptureVideoDataOutput>,
     // we have to keep a reference to the delegate to prevent it from being dropped
     _delegate: Id<OutputDelegate>,
+    // an AVCaptureMetadataOutput and its delegate, only with `Backend::Native`
+    metadata_output: Option<(Id<AnyObject>, Id<MetadataDelegate>)>,
+    // an AVCaptureMovieFileOutput, added to the session alongside `output` the first time we record
+    movie_output: Option<Id<AnyObject>>,
+    recording_delegate: Id<RecordingDelegate>,
+    // an AVCapturePhotoOutput and its delegate, added alongside `output` the first time a still is captured
+    photo_output: Option<(Id<AnyObject>, Id<PhotoDelegate>)>,
     running: bool,
 }
 
+/// A still from `DeviceCapture::capture_photo`, or why it couldn't be taken.
+pub type PhotoResult = Result<RgbaImage, String>;
+
 impl DeviceCapture {
-    pub fn start(info: &DeviceInfo, decoder: Decoder) -> Result<DeviceCapture, String> {
+    /// `start`, retrying with exponential backoff as configured in `options`, e.g. when the camera isn't
+    /// ready straight after wake. `sleep` waits out each delay, blocking or not as the caller needs.
+    pub async fn start_with_retries<F: Future>(
+        info: &DeviceInfo,
+        decoder: &Decoder,
+        options: &CaptureOptions,
+        sleep: impl Fn(Duration) -> F,
+    ) -> Result<DeviceCapture, String> {
+        let mut attempt = 0;
+        loop {
+            match Self::start(info, decoder.clone(), options) {
+                Err(err) if attempt < options.start_retries => {
+                    log::warn!("failed to start camera, retrying: {}", err);
+                    sleep(options.retry_delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn start(info: &DeviceInfo, decoder: Decoder, options: &CaptureOptions) -> Result<DeviceCapture, String> {
         let session = AVCaptureSession::new();
         let id = NSString::from_str(&info.id);
         let device = AVCaptureDevice::device_with_unique_id(&id).ok_or("Device not found")?;
@@ -73,13 +431,17 @@ impl DeviceCapture {
         let input =
             AVCaptureDeviceInput::from_device(&device).map_err(|err| format!("Failed to create input: {}", err))?;
         let mut delegate = OutputDelegate::new();
-        let queue = Queue::new("com.video-capture.output", QueueAttribute::Serial);
+        let queue_attribute = match options.concurrent_queue {
+            true => QueueAttribute::Concurrent,
+            false => QueueAttribute::Serial,
+        };
+        let queue = Queue::new(&options.queue_label, queue_attribute);
         let ivars = delegate.ivars_mut();
 
-        ivars.decoder = Some(decoder);
+        ivars.decoder = Some(decoder.clone());
 
         output.set_sample_buffer_delegate(ProtocolObject::from_ref(&*delegate), &queue);
-        output.set_always_discards_late_video_frames(true);
+        output.set_always_discards_late_video_frames(options.discard_late_frames);
 
         if session.can_add_input(&input) && session.can_add_output(&output) {
             session.add_input(&input);
@@ -88,22 +450,401 @@ impl DeviceCapture {
             return Err("cannot add input or output".to_string());
         }
 
-        session.begin_configuration();
+        let mut metadata_output = None;
+        if decoder.options().backend == Backend::Native {
+            match add_metadata_output(&session, &decoder, &queue) {
+                Ok(output) => metadata_output = Some(output),
+                Err(err) => {
+                    log::warn!("native detection unavailable, using zxing-cpp: {}", err);
+                    decoder.set_options(DecodeOptions {
+                        backend: Backend::Zxing,
+                        ..decoder.options()
+                    });
+                }
+            }
+        }
 
+        session.begin_configuration();
+        let available = output_pixel_formats(&output);
+        log::debug!(
+            "available pixel formats: {}",
+            available
+                .iter()
+                .map(|&code| fourcc(code))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let pixel_format = match options.pixel_format {
+            Some(format) if available.contains(&format.code()) => Some(format),
+            Some(format) => {
+                log::warn!(
+                    "pixel format {} not supported by {}, choosing another",
+                    format,
+                    info.name
+                );
+                PixelFormat::preferred(&available)
+            }
+            None => PixelFormat::preferred(&available),
+        };
+        match pixel_format {
+            Some(format) => {
+                set_output_pixel_format(&output, format);
+                log::info!("capturing {} frames", format);
+            }
+            // the device's default, which the decoder may or may not understand
+            None => log::warn!("{} supports none of the pixel formats the decoder handles", info.name),
+        }
         session.commit_configuration();
         session.start_running();
 
-        Ok(Self {
+        let capture = Self {
             session,
+            device,
             input,
             output,
             _delegate: delegate,
+            metadata_output,
+            movie_output: None,
+            recording_delegate: RecordingDelegate::new(),
+            photo_output: None,
             running: true,
+        };
+        if options.torch_pulse && !capture.has_torch() {
+            log::warn!("{} has no torch to pulse", info.name);
+        }
+        // after starting, since the session applies its preset's frame rate as it starts
+        if let Some(fps) = options.frame_rate {
+            match capture.set_frame_rate(fps) {
+                Ok(fps) => log::info!("frame rate set to {} fps", fps),
+                Err(err) => log::warn!("failed to set frame rate: {}", err),
+            }
+        }
+        Ok(capture)
+    }
+
+    /// Start recording the camera to a QuickTime movie at `path`, replacing any existing file.
+    ///
+    /// The movie output runs alongside the video data output, so frames keep reaching the decoder.
+    pub fn start_recording(&mut self, path: &Path) -> Result<(), String> {
+        if self.is_recording() {
+            return Err("already recording".to_string());
+        }
+        // AVFoundation refuses to overwrite an existing file
+        if path.exists() {
+            std::fs::remove_file(path).map_err(|err| format!("Failed to replace {}: {}", path.display(), err))?;
+        }
+        if self.movie_output.is_none() {
+            let movie_output: Id<AnyObject> = unsafe { msg_send_id![class!(AVCaptureMovieFileOutput), new] };
+            self.session.begin_configuration();
+            let can_add: bool = unsafe { msg_send![&*self.session, canAddOutput: &*movie_output] };
+            if can_add {
+                let _: () = unsafe { msg_send![&*self.session, addOutput: &*movie_output] };
+            }
+            self.session.commit_configuration();
+            if !can_add {
+                return Err("cannot add movie output".to_string());
+            }
+            self.movie_output = Some(movie_output);
+        }
+        let movie_output = self.movie_output.as_ref().expect("movie output added above");
+        let path = NSString::from_str(&path.to_string_lossy());
+        unsafe {
+            let url = NSURL::fileURLWithPath(&path);
+            let _: () = msg_send![
+                &**movie_output,
+                startRecordingToOutputFileURL: &*url,
+                recordingDelegate: &*self.recording_delegate
+            ];
+        }
+        Ok(())
+    }
+
+    /// Stop recording, the movie is finished writing asynchronously.
+    pub fn stop_recording(&self) {
+        if let Some(movie_output) = &self.movie_output {
+            let _: () = unsafe { msg_send![&**movie_output, stopRecording] };
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        match &self.movie_output {
+            Some(movie_output) => unsafe { msg_send![&**movie_output, isRecording] },
+            None => false,
+        }
+    }
+
+    /// Capture a still at the largest photo size the camera's current format supports, usually well
+    /// beyond the video frames', for codes too small to resolve in the video stream.
+    ///
+    /// The photo output is added the first time, alongside the video data output so the preview keeps
+    /// running. The still is sent to the returned receiver once it's been processed, which takes a
+    /// moment.
+    pub fn capture_photo(&mut self) -> Result<mpsc::Receiver<PhotoResult>, String> {
+        if self.photo_output.is_none() {
+            let photo_output: Id<AnyObject> = unsafe { msg_send_id![class!(AVCapturePhotoOutput), new] };
+            self.session.begin_configuration();
+            let can_add: bool = unsafe { msg_send![&*self.session, canAddOutput: &*photo_output] };
+            if can_add {
+                let _: () = unsafe { msg_send![&*self.session, addOutput: &*photo_output] };
+            }
+            self.session.commit_configuration();
+            if !can_add {
+                return Err("cannot add photo output".to_string());
+            }
+            self.photo_output = Some((photo_output, PhotoDelegate::new()));
+        }
+        let (photo_output, delegate) = self.photo_output.as_ref().expect("photo output added above");
+
+        let (sender, receiver) = mpsc::channel();
+        *lock(&delegate.ivars().sender) = Some(sender);
+        unsafe {
+            // JPEG rather than the default HEVC, which the image crate can't read
+            let codec_key = NSString::from_str("AVVideoCodecKey");
+            let format = NSDictionary::from_vec(&[&*codec_key], vec![NSString::from_str("jpeg")]);
+            let settings: Id<AnyObject> =
+                msg_send_id![class!(AVCapturePhotoSettings), photoSettingsWithFormat: &*format];
+            // photos are only as large as the video frames unless asked otherwise, macOS 13+
+            if let Some(dimensions) = self.max_photo_dimensions() {
+                let _: () = msg_send![&**photo_output, setMaxPhotoDimensions: dimensions];
+                let _: () = msg_send![&*settings, setMaxPhotoDimensions: dimensions];
+            }
+            let _: () = msg_send![&**photo_output, capturePhotoWithSettings: &*settings, delegate: &**delegate];
+        }
+        Ok(receiver)
+    }
+
+    /// The largest photo the current format can take, `None` before macOS 13.
+    fn max_photo_dimensions(&self) -> Option<CMVideoDimensions> {
+        let format: Id<AnyObject> = unsafe { msg_send_id![&*self.device, activeFormat] };
+        let supported: bool = unsafe { msg_send![&*format, respondsToSelector: sel!(supportedMaxPhotoDimensions)] };
+        if !supported {
+            return None;
+        }
+        let dimensions: Id<NSArray<AnyObject>> = unsafe { msg_send_id![&*format, supportedMaxPhotoDimensions] };
+        dimensions
+            .iter()
+            .map(|value| -> CMVideoDimensions { unsafe { msg_send![value, CMVideoDimensionsValue] } })
+            .max_by_key(|dimensions| dimensions.width as i64 * dimensions.height as i64)
+    }
+
+    /// Change how the camera focuses, locking the device for configuration while doing so.
+    ///
+    /// Returns an error if the device doesn't support the requested mode.
+    pub fn set_focus(&self, mode: FocusMode) -> Result<(), String> {
+        let (av_mode, supported) = match mode {
+            FocusMode::Auto => (
+                AV_CAPTURE_FOCUS_MODE_CONTINUOUS_AUTO_FOCUS,
+                self.focus_mode_supported(AV_CAPTURE_FOCUS_MODE_CONTINUOUS_AUTO_FOCUS),
+            ),
+            FocusMode::Locked => (
+                AV_CAPTURE_FOCUS_MODE_LOCKED,
+                self.focus_mode_supported(AV_CAPTURE_FOCUS_MODE_LOCKED),
+            ),
+            FocusMode::Manual(lens_position) => {
+                if !(0.0..=1.0).contains(&lens_position) {
+                    return Err(format!("Lens position {} out of range 0.0 to 1.0", lens_position));
+                }
+                let supported: bool =
+                    unsafe { msg_send![&*self.device, isLockingFocusWithCustomLensPositionSupported] };
+                (AV_CAPTURE_FOCUS_MODE_LOCKED, supported)
+            }
+        };
+        if !supported {
+            return Err(format!("Focus mode {:?} not supported by this device", mode));
+        }
+
+        self.configure(|device| unsafe {
+            match mode {
+                FocusMode::Manual(lens_position) => {
+                    let completion_handler: Option<&Block<dyn Fn()>> = None;
+                    let _: () = msg_send![
+                        device,
+                        setFocusModeLockedWithLensPosition: lens_position,
+                        completionHandler: completion_handler
+                    ];
+                }
+                _ => {
+                    let _: () = msg_send![device, setFocusMode: av_mode];
+                }
+            }
         })
     }
 
+    /// Whether the device has a torch, Mac cameras don't but iPhones used as Continuity Cameras do.
+    pub fn has_torch(&self) -> bool {
+        unsafe { msg_send![&*self.device, hasTorch] }
+    }
+
+    /// Turn the torch on or off, returns an error if the device doesn't have one.
+    pub fn set_torch(&self, on: bool) -> Result<(), String> {
+        if !self.has_torch() {
+            return Err("This camera has no torch".to_string());
+        }
+        let av_mode = match on {
+            true => AV_CAPTURE_TORCH_MODE_ON,
+            false => AV_CAPTURE_TORCH_MODE_OFF,
+        };
+        self.configure(|device| unsafe {
+            let _: () = msg_send![device, setTorchMode: av_mode];
+        })
+    }
+
+    /// Read the device's current exposure duration, ISO and lens position, which change continuously
+    /// under auto exposure and focus, to help tell a camera struggling with the light from a decoding
+    /// problem. Cheap, but there's no need to call it more than about once a second.
+    pub fn exposure(&self) -> Exposure {
+        let device = &*self.device;
+        unsafe {
+            Exposure {
+                duration: self
+                    .responds_to(sel!(exposureDuration))
+                    .then(|| cm_time_seconds(msg_send![device, exposureDuration]))
+                    .flatten(),
+                iso: self.responds_to(sel!(ISO)).then(|| msg_send![device, ISO]),
+                lens_position: self
+                    .responds_to(sel!(lensPosition))
+                    .then(|| msg_send![device, lensPosition]),
+            }
+        }
+    }
+
+    /// Whether the device implements a method, several are missing on older versions of macOS.
+    fn responds_to(&self, selector: Sel) -> bool {
+        unsafe { msg_send![&*self.device, respondsToSelector: selector] }
+    }
+
+    fn focus_mode_supported(&self, av_mode: NSInteger) -> bool {
+        unsafe { msg_send![&*self.device, isFocusModeSupported: av_mode] }
+    }
+
+    /// List the capture formats the device supports, with their dimensions and frame rate ranges.
+    pub fn formats(&self) -> Vec<CaptureFormat> {
+        self.av_formats()
+            .iter()
+            .enumerate()
+            .map(|(index, format)| unsafe {
+                let description: CMFormatDescriptionRef = msg_send![format, formatDescription];
+                let dimensions = CMVideoFormatDescriptionGetDimensions(description);
+                CaptureFormat {
+                    index,
+                    width: dimensions.width,
+                    height: dimensions.height,
+                    frame_rate_ranges: frame_rate_ranges(format),
+                }
+            })
+            .collect()
+    }
+
+    /// Switch the device to one of the formats from `formats`, optionally at a fixed frame rate,
+    /// otherwise at the format's maximum.
+    ///
+    /// If the device doesn't accept the format, the previous format and frame rate are restored.
+    pub fn set_format(&self, format: &CaptureFormat, fps: Option<f64>) -> Result<(), String> {
+        let av_formats = self.av_formats();
+        let av_format = av_formats.get(format.index).ok_or("Format not found")?;
+        let fps = match fps {
+            Some(fps) if format.supports_frame_rate(fps) => fps,
+            Some(fps) => return Err(format!("{} fps not supported by format", fps)),
+            None => format.max_frame_rate().ok_or("Format has no frame rates")?,
+        };
+        // express the frame duration in milliseconds so fractional rates like 29.97 survive
+        let frame_duration = CMTime::make(1000, (fps * 1000.).round() as i32);
+
+        self.configure(|device| unsafe {
+            let previous_format: Id<AnyObject> = msg_send_id![device, activeFormat];
+            let previous_duration: CMTime = msg_send![device, activeVideoMinFrameDuration];
+
+            let _: () = msg_send![device, setActiveFormat: av_format];
+            let active_format: Id<AnyObject> = msg_send_id![device, activeFormat];
+            if !std::ptr::eq(&*active_format, av_format) {
+                let _: () = msg_send![device, setActiveFormat: &*previous_format];
+                let _: () = msg_send![device, setActiveVideoMinFrameDuration: previous_duration];
+                return Err("Device rejected format".to_string());
+            }
+            let _: () = msg_send![device, setActiveVideoMinFrameDuration: frame_duration];
+            Ok(())
+        })?
+    }
+
+    /// Run at `fps` frames per second, clamped to the nearest rate the active format supports, returning
+    /// the rate set. Fewer frames means less work for both the camera and the decoder.
+    pub fn set_frame_rate(&self, fps: f64) -> Result<f64, String> {
+        if fps.is_nan() || fps <= 0. {
+            return Err(format!("invalid frame rate {}", fps));
+        }
+        if !self.responds_to(sel!(setActiveVideoMinFrameDuration:))
+            || !self.responds_to(sel!(setActiveVideoMaxFrameDuration:))
+        {
+            return Err("Device doesn't support setting the frame rate".to_string());
+        }
+        let active_format: Id<AnyObject> = unsafe { msg_send_id![&*self.device, activeFormat] };
+        let fps = clamp_frame_rate(&frame_rate_ranges(&active_format), fps).ok_or("Format has no frame rates")?;
+        // express the frame duration in milliseconds so fractional rates like 29.97 survive
+        let frame_duration = CMTime::make(1000, (fps * 1000.).round() as i32);
+        self.configure(|device| unsafe {
+            let _: () = msg_send![device, setActiveVideoMinFrameDuration: frame_duration];
+            let _: () = msg_send![device, setActiveVideoMaxFrameDuration: frame_duration];
+        })?;
+        Ok(fps)
+    }
+
+    /// The Core Video pixel formats the camera can deliver frames in, see `fourcc` to show them.
+    pub fn available_pixel_formats(&self) -> Vec<u32> {
+        output_pixel_formats(&self.output)
+    }
+
+    /// The pixel format frames are being delivered in, `None` if it's the device's default or one the
+    /// decoder doesn't handle.
+    pub fn pixel_format(&self) -> Option<PixelFormat> {
+        let key = unsafe { &*(kCVPixelBufferPixelFormatTypeKey as *const NSString) };
+        let settings: Option<Id<NSDictionary<NSString, NSNumber>>> =
+            unsafe { msg_send_id![&*self.output, videoSettings] };
+        PixelFormat::from_code(settings?.get(key)?.as_u32())
+    }
+
+    /// Ask for frames in `format`, returning an error if the camera can't deliver it.
+    pub fn set_pixel_format(&self, format: PixelFormat) -> Result<(), String> {
+        if !self.available_pixel_formats().contains(&format.code()) {
+            return Err(format!("Pixel format {} not supported by this device", format));
+        }
+        self.session.begin_configuration();
+        set_output_pixel_format(&self.output, format);
+        self.session.commit_configuration();
+        Ok(())
+    }
+
+    fn av_formats(&self) -> Id<NSArray<AnyObject>> {
+        unsafe { msg_send_id![&*self.device, formats] }
+    }
+
+    /// Run `f` with the device locked for configuration, as AVFoundation requires for changing its settings.
+    fn configure<T>(&self, f: impl FnOnce(&AVCaptureDevice) -> T) -> Result<T, String> {
+        let locked: Result<(), Id<NSError>> = unsafe { msg_send![&*self.device, lockForConfiguration: _] };
+        locked.map_err(|err| {
+            format!(
+                "Failed to lock device for configuration: {}",
+                err.localizedDescription()
+            )
+        })?;
+        let result = f(&self.device);
+        unsafe {
+            let _: () = msg_send![&*self.device, unlockForConfiguration];
+        }
+        Ok(result)
+    }
+
     pub fn stop(&mut self) {
         if self.running {
+            self.stop_recording();
+            if let Some(movie_output) = self.movie_output.take() {
+                let _: () = unsafe { msg_send![&*self.session, removeOutput: &*movie_output] };
+            }
+            if let Some((metadata_output, _)) = self.metadata_output.take() {
+                let _: () = unsafe { msg_send![&*self.session, removeOutput: &*metadata_output] };
+            }
+            if let Some((photo_output, _)) = self.photo_output.take() {
+                let _: () = unsafe { msg_send![&*self.session, removeOutput: &*photo_output] };
+            }
             self.session.remove_output(&self.output);
             self.session.stop_running();
             self.session.remove_input(&self.input);
@@ -118,6 +859,135 @@ impl Drop for DeviceCapture {
     }
 }
 
+/// Add an `AVCaptureMetadataOutput` to the session so the camera's own detector reports codes in the
+/// decoder's enabled formats, for formats the detector supports.
+fn add_metadata_output(
+    session: &AVCaptureSession,
+    decoder: &Decoder,
+    queue: &Queue,
+) -> Result<(Id<AnyObject>, Id<MetadataDelegate>), String> {
+    // AVCaptureMetadataOutput is only available on macOS 13 and later
+    let class = AnyClass::get("AVCaptureMetadataOutput").ok_or("AVCaptureMetadataOutput not available")?;
+    let output: Id<AnyObject> = unsafe { msg_send_id![class, new] };
+    let can_add: bool = unsafe { msg_send![session, canAddOutput: &*output] };
+    if !can_add {
+        return Err("cannot add metadata output".to_string());
+    }
+    let _: () = unsafe { msg_send![session, addOutput: &*output] };
+
+    // the available types are only known once the output is in the session, and setting any others throws
+    let available: Id<NSArray<NSString>> = unsafe { msg_send_id![&*output, availableMetadataObjectTypes] };
+    let available: Vec<String> = available.iter().map(|available| available.to_string()).collect();
+    let formats = decoder.options().formats;
+    // any enabled format the detector can't find would silently never scan, zxing-cpp finds them all
+    let unsupported: Vec<String> = formats
+        .iter()
+        .filter(|format| {
+            !format
+                .metadata_type()
+                .is_some_and(|metadata_type| available.iter().any(|available| available == metadata_type))
+        })
+        .map(|format| format.barcode_format().to_string())
+        .collect();
+    if !unsupported.is_empty() {
+        let _: () = unsafe { msg_send![session, removeOutput: &*output] };
+        return Err(format!(
+            "{} not supported by the camera's detector",
+            unsupported.join(", ")
+        ));
+    }
+    let types: Vec<Id<NSString>> = formats
+        .iter()
+        .filter_map(|format| format.metadata_type())
+        .map(NSString::from_str)
+        .collect();
+
+    let mut delegate = MetadataDelegate::new();
+    delegate.ivars_mut().decoder = Some(decoder.clone());
+    let types = NSArray::from_vec(types);
+    unsafe {
+        let _: () = msg_send![&*output, setMetadataObjectTypes: &*types];
+        let _: () = msg_send![
+            &*output,
+            setMetadataObjectsDelegate: &*delegate,
+            queue: queue.as_raw() as *mut AnyObject
+        ];
+    }
+    Ok((output, delegate))
+}
+
+/// Read an `AVMetadataMachineReadableCodeObject`, `None` for other metadata, formats we don't know or
+/// codes with no text.
+unsafe fn read_detection(object: &AnyObject) -> Option<Detection> {
+    let is_code: bool = msg_send![object, isKindOfClass: class!(AVMetadataMachineReadableCodeObject)];
+    if !is_code {
+        return None;
+    }
+    let metadata_type: Id<NSString> = msg_send_id![object, type];
+    let format = CodeFormat::from_metadata_type(&metadata_type.to_string())?;
+    let text: Option<Id<NSString>> = msg_send_id![object, stringValue];
+    // each corner is a CGPoint dictionary representation, as fractions of the frame size
+    let corners: Id<NSArray<AnyObject>> = msg_send_id![object, corners];
+    let corners: Vec<[f64; 2]> = corners
+        .iter()
+        .map(|corner| Some([point_value(corner, "X")?, point_value(corner, "Y")?]))
+        .collect::<Option<_>>()?;
+    Some(Detection {
+        text: text?.to_string(),
+        format,
+        corners: corners.try_into().ok()?,
+    })
+}
+
+unsafe fn point_value(point: &AnyObject, key: &str) -> Option<f64> {
+    let value: Option<Id<AnyObject>> = msg_send_id![point, objectForKey: &*NSString::from_str(key)];
+    value.map(|value| msg_send![&*value, doubleValue])
+}
+
+fn output_pixel_formats(output: &AVCaptureVideoDataOutput) -> Vec<u32> {
+    let formats: Id<NSArray<NSNumber>> = unsafe { msg_send_id![output, availableVideoCVPixelFormatTypes] };
+    formats.iter().map(|format| format.as_u32()).collect()
+}
+
+fn set_output_pixel_format(output: &AVCaptureVideoDataOutput, format: PixelFormat) {
+    let key = unsafe { &*(kCVPixelBufferPixelFormatTypeKey as *const NSString) };
+    let settings = NSDictionary::from_vec(&[key], vec![NSNumber::new_u32(format.code())]);
+    let _: () = unsafe { msg_send![output, setVideoSettings: &*settings] };
+}
+
+// kCMTimeFlags_Valid
+const CM_TIME_FLAGS_VALID: u32 = 1;
+
+/// The frame rates an `AVCaptureDeviceFormat` supports.
+fn frame_rate_ranges(format: &AnyObject) -> Vec<FrameRateRange> {
+    let ranges: Id<NSArray<AnyObject>> = unsafe { msg_send_id![format, videoSupportedFrameRateRanges] };
+    ranges
+        .iter()
+        .map(|range| unsafe {
+            FrameRateRange {
+                min: msg_send![range, minFrameRate],
+                max: msg_send![range, maxFrameRate],
+            }
+        })
+        .collect()
+}
+
+/// The supported rate closest to `fps`, `None` if there are no ranges.
+fn clamp_frame_rate(ranges: &[FrameRateRange], fps: f64) -> Option<f64> {
+    ranges
+        .iter()
+        .map(|range| fps.clamp(range.min, range.max))
+        .min_by(|a, b| (a - fps).abs().total_cmp(&(b - fps).abs()))
+}
+
+/// Convert a `CMTime` to seconds, `None` if it's invalid or indefinite.
+pub(crate) fn cm_time_seconds(time: CMTime) -> Option<f64> {
+    if time.flags & CM_TIME_FLAGS_VALID == 0 || time.timescale == 0 {
+        return None;
+    }
+    Some(time.value as f64 / time.timescale as f64)
+}
+
 #[derive(Default)]
 struct OutputDelegateIvars {
     decoder: Option<Decoder>,
@@ -150,11 +1020,15 @@ declare_class!(
             let video_frame = sample_buffer
                 .get_image_buffer()
                 .and_then(|image_buffer| image_buffer.downcast::<CVPixelBuffer>())
-                .and_then(|pixel_buffer| MediaFrame::from_pixel_buffer(&pixel_buffer).ok());
+                .and_then(|pixel_buffer| {
+                    let pixel_format = PixelFormat::from_code(pixel_buffer.get_pixel_format());
+                    Some((MediaFrame::from_pixel_buffer(&pixel_buffer).ok()?, pixel_format))
+                });
 
-            if let Some(video_frame) = video_frame {
+            if let Some((video_frame, pixel_format)) = video_frame {
                 let decoder = self.ivars().decoder.as_ref().unwrap();
-                decoder.decode(video_frame);
+                let timestamp = cm_time_seconds(sample_buffer.get_presentation_time_stamp());
+                decoder.decode(video_frame, pixel_format, timestamp);
             }
         }
     }
@@ -165,6 +1039,18 @@ declare_class!(
             let this = this.set_ivars(OutputDelegateIvars::default());
             unsafe { msg_send_id![super(this), init] }
         }
+
+        #[method(captureOutput:didDropSampleBuffer:fromConnection:)]
+        unsafe fn capture_output_did_drop_sample_buffer(
+            &self,
+            _capture_output: &AVCaptureOutput,
+            _sample_buffer: CMSampleBufferRef,
+            _connection: &AVCaptureConnection,
+        ) {
+            if let Some(decoder) = self.ivars().decoder.as_ref() {
+                decoder.record_dropped_frame();
+            }
+        }
     }
 );
 
@@ -174,3 +1060,155 @@ extern_methods!(
         pub fn new() -> Id<Self>;
     }
 );
+
+#[derive(Default)]
+struct MetadataDelegateIvars {
+    decoder: Option<Decoder>,
+}
+
+declare_class!(
+    /// Receives `AVCaptureMetadataOutputObjectsDelegate` callbacks with the codes the camera detected.
+    struct MetadataDelegate;
+
+    unsafe impl ClassType for MetadataDelegate {
+        type Super = NSObject;
+        type Mutability = mutability::Mutable;
+        const NAME: &'static str = "MetadataObjectsDelegate";
+    }
+
+    impl DeclaredClass for MetadataDelegate {
+        type Ivars = MetadataDelegateIvars;
+    }
+
+    unsafe impl NSObjectProtocol for MetadataDelegate {}
+
+    unsafe impl MetadataDelegate {
+        #[method_id(init)]
+        fn init(this: Allocated<Self>) -> Option<Id<Self>> {
+            let this = this.set_ivars(MetadataDelegateIvars::default());
+            unsafe { msg_send_id![super(this), init] }
+        }
+
+        #[method(captureOutput:didOutputMetadataObjects:fromConnection:)]
+        unsafe fn capture_output_did_output_metadata_objects(
+            &self,
+            _capture_output: &AnyObject,
+            objects: &NSArray<AnyObject>,
+            _connection: &AnyObject,
+        ) {
+            let detections = objects.iter().filter_map(|object| read_detection(object)).collect();
+            let decoder = self.ivars().decoder.as_ref().unwrap();
+            decoder.record_detections(detections);
+        }
+    }
+);
+
+extern_methods!(
+    unsafe impl MetadataDelegate {
+        #[method_id(new)]
+        pub fn new() -> Id<Self>;
+    }
+);
+
+declare_class!(
+    /// Receives `AVCaptureFileOutputRecordingDelegate` callbacks when a movie finishes writing.
+    struct RecordingDelegate;
+
+    unsafe impl ClassType for RecordingDelegate {
+        type Super = NSObject;
+        type Mutability = mutability::InteriorMutable;
+        const NAME: &'static str = "RecordingDelegate";
+    }
+
+    impl DeclaredClass for RecordingDelegate {}
+
+    unsafe impl NSObjectProtocol for RecordingDelegate {}
+
+    unsafe impl RecordingDelegate {
+        #[method(captureOutput:didFinishRecordingToOutputFileURL:fromConnections:error:)]
+        unsafe fn capture_output_did_finish_recording(
+            &self,
+            _capture_output: &AnyObject,
+            _url: &NSURL,
+            _connections: &NSArray<AnyObject>,
+            error: Option<&NSError>,
+        ) {
+            // AVFoundation also reports an error when recording stopped normally but the
+            // file is still usable, so this is only a warning
+            match error {
+                Some(error) => log::warn!("recording finished with error: {}", error.localizedDescription()),
+                None => log::info!("recording finished"),
+            }
+        }
+    }
+);
+
+extern_methods!(
+    unsafe impl RecordingDelegate {
+        #[method_id(new)]
+        pub fn new() -> Id<Self>;
+    }
+);
+
+#[derive(Default)]
+struct PhotoDelegateIvars {
+    // where the still being captured is sent, replaced for each capture
+    sender: Mutex<Option<mpsc::Sender<PhotoResult>>>,
+}
+
+declare_class!(
+    /// Receives `AVCapturePhotoCaptureDelegate` callbacks with stills from `DeviceCapture::capture_photo`.
+    struct PhotoDelegate;
+
+    unsafe impl ClassType for PhotoDelegate {
+        type Super = NSObject;
+        type Mutability = mutability::InteriorMutable;
+        const NAME: &'static str = "PhotoCaptureDelegate";
+    }
+
+    impl DeclaredClass for PhotoDelegate {
+        type Ivars = PhotoDelegateIvars;
+    }
+
+    unsafe impl NSObjectProtocol for PhotoDelegate {}
+
+    unsafe impl PhotoDelegate {
+        #[method_id(init)]
+        fn init(this: Allocated<Self>) -> Option<Id<Self>> {
+            let this = this.set_ivars(PhotoDelegateIvars::default());
+            unsafe { msg_send_id![super(this), init] }
+        }
+
+        #[method(captureOutput:didFinishProcessingPhoto:error:)]
+        unsafe fn capture_output_did_finish_processing_photo(
+            &self,
+            _capture_output: &AnyObject,
+            photo: &AnyObject,
+            error: Option<&NSError>,
+        ) {
+            let result = match error {
+                Some(error) => Err(error.localizedDescription().to_string()),
+                None => read_photo(photo),
+            };
+            if let Some(sender) = lock(&self.ivars().sender).take() {
+                // the receiver is gone if whoever asked for the still stopped waiting
+                sender.send(result).ok();
+            }
+        }
+    }
+);
+
+extern_methods!(
+    unsafe impl PhotoDelegate {
+        #[method_id(new)]
+        pub fn new() -> Id<Self>;
+    }
+);
+
+/// Decode an `AVCapturePhoto` captured as JPEG.
+unsafe fn read_photo(photo: &AnyObject) -> PhotoResult {
+    let data: Option<Id<NSData>> = msg_send_id![photo, fileDataRepresentation];
+    let data = data.ok_or("photo has no data")?;
+    let image = image::load_from_memory(data.bytes()).map_err(|err| format!("failed to read photo: {}", err))?;
+    Ok(image.into_rgba8())
+}
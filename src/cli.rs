@@ -0,0 +1,558 @@
+use std::fs;
+use std::future::{self, Future};
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::task::{self, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use clap::Parser;
+use zxingcpp::BarcodeReader;
+
+use crate::camera::{DeviceCapture, DeviceInfo, Facing};
+use crate::decode::{lock, Decoder};
+use crate::generate::generate;
+use crate::output::{Output, OutputFormat};
+use crate::qr::{barcode_reader, decode_image, Backend, CodeFormat, DecodeOptions, QRCode};
+use crate::sanitize::escape_control;
+use crate::settings::Settings;
+use crate::video::{VideoPlayback, VideoSource};
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    long_version = long_version(),
+    about = "Scan QR codes from your camera",
+    after_help = "Options showing [env: QRCAM_...] can also be set with that environment variable, e.g. for \
+launchd or systemd. Command line flags take precedence over environment variables, which take precedence over \
+the settings file, which takes precedence over the defaults. Neither flags nor environment variables are saved to \
+the settings file."
+)]
+pub struct Args {
+    /// Print the available cameras and exit
+    #[arg(long)]
+    pub list_cameras: bool,
+
+    /// Check decoding works without a camera by decoding a generated QR code, print PASS or FAIL and exit
+    #[arg(long)]
+    pub self_test: bool,
+
+    /// Open the camera without a window, print the first code(s) decoded and exit
+    #[arg(long)]
+    pub scan_once: bool,
+
+    /// With --scan-once, give up and exit non-zero after this many seconds
+    #[arg(long, requires = "scan_once")]
+    pub timeout: Option<f64>,
+
+    /// Decode these images, printing the code(s) in each prefixed with its path, and exit. Exits non-zero
+    /// only if none of them could be decoded
+    #[arg(value_name = "FILE", conflicts_with_all = ["scan_once", "decode_dir", "decode_stdin", "video"])]
+    pub files: Vec<PathBuf>,
+
+    /// Decode every image in a directory (recursively), printing a JSON line per file
+    #[arg(long, value_name = "PATH")]
+    pub decode_dir: Option<PathBuf>,
+
+    /// Decode an image read from stdin, as raw bytes in any supported format or base64, printing the code(s) found
+    #[arg(long)]
+    pub decode_stdin: bool,
+
+    /// On quit, write every code scanned this session to a file, as JSON if it ends `.json`, otherwise CSV
+    #[arg(long, value_name = "PATH")]
+    pub export: Option<PathBuf>,
+
+    /// Record the camera to a QuickTime movie at this path while the window is open, cmd-shift-r stops and restarts it
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+
+    /// Count how many times each code comes into view, and on quit write the counts to PATH if given, as JSON if
+    /// it ends `.json`, otherwise CSV
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    pub tally: Option<Option<PathBuf>>,
+
+    /// Include positions normalized to 0.0-1.0 of the frame size alongside pixel positions in JSON output
+    #[arg(long)]
+    pub normalized_positions: bool,
+
+    /// How --scan-once, --decode-stdin, --decode-dir and FILEs print codes [default: json for --decode-dir,
+    /// otherwise text]
+    #[arg(long, value_enum)]
+    pub output_format: Option<OutputFormat>,
+
+    /// Run a shell command for each newly scanned code, with `{}` replaced by the code's text (quoted for the
+    /// shell). Scanned text is untrusted, don't pass it to anything that would evaluate it
+    #[arg(long, value_name = "COMMAND")]
+    pub on_scan: Option<String>,
+
+    /// Keep the window above other apps' windows
+    #[arg(long, env = "QRCAM_ALWAYS_ON_TOP")]
+    pub always_on_top: bool,
+
+    /// Show a desktop notification for each newly scanned code, only possible when run from an app bundle
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Most codes reported per frame, lower is faster on busy frames [default: 8]
+    #[arg(long, value_name = "N")]
+    pub max_results: Option<u32>,
+
+    /// Downscale frames so their longest side is at most this many pixels before decoding (faster on large frames)
+    #[arg(long, value_name = "PIXELS")]
+    pub max_decode_dimension: Option<u32>,
+
+    /// Decode each frame as this many overlapping bands on separate threads, can be faster with many codes
+    /// in frame [default: 1]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub decode_threads: Option<u32>,
+
+    /// Decode text in this character set (e.g. shift_jis or iso-8859-1) rather than the one the code declares or
+    /// zxing-cpp guesses
+    #[arg(long, value_name = "LABEL", value_parser = parse_charset)]
+    pub charset: Option<String>,
+
+    /// Find codes in camera frames with zxing-cpp or the camera's own detector (macOS 13+), falling back to
+    /// zxing-cpp if the camera's detector is unavailable or can't find every enabled format [default: zxing]
+    #[arg(long, value_enum, env = "QRCAM_DECODER")]
+    pub decoder: Option<Backend>,
+
+    /// Only look for these formats, comma separated [default: qr-code]
+    #[arg(long, value_enum, value_delimiter = ',', env = "QRCAM_FORMATS")]
+    pub formats: Vec<CodeFormat>,
+
+    /// Mirror the preview and the frames decoded, selfie style [default: true]
+    #[arg(long, value_name = "BOOL", env = "QRCAM_MIRROR")]
+    pub mirror: Option<bool>,
+
+    /// Milliseconds the decode thread waits between frames, lower reduces latency but uses more CPU
+    /// [default: 51]
+    #[arg(long, value_name = "MS", env = "QRCAM_DECODE_INTERVAL")]
+    pub decode_interval: Option<u64>,
+
+    /// When a frame has no codes, retry with contrast and perspective correction (slower)
+    #[arg(long)]
+    pub deskew: bool,
+
+    /// Stretch each frame's contrast before decoding, for codes on glossy or low-contrast surfaces (slower)
+    #[arg(long)]
+    pub normalize_contrast: bool,
+
+    /// Don't look for light-on-dark codes, faster when all codes are dark-on-light
+    #[arg(long)]
+    pub no_invert: bool,
+
+    /// Show a grey preview rather than colour, skipping colour conversion of each frame to save CPU
+    #[arg(long)]
+    pub grayscale: bool,
+
+    /// Experimental: flash the camera's torch on and off, only decoding frames taken with it off, for glossy
+    /// codes its glare hides. Only cameras with a torch, like an iPhone used as a Continuity Camera
+    #[arg(long)]
+    pub torch_pulse: bool,
+
+    /// Keep frames that arrive while the previous one is being processed, rather than dropping them
+    #[arg(long)]
+    pub keep_late_frames: bool,
+
+    /// Deliver camera frames on a concurrent queue
+    #[arg(long)]
+    pub concurrent_queue: bool,
+
+    /// Only report a code once it's been decoded in K of the last N frames, e.g. 2/3, to suppress
+    /// one-frame misreads [default: 1/1]
+    #[arg(long, value_name = "K/N", value_parser = parse_confirm)]
+    pub confirm: Option<(u32, u32)>,
+
+    /// Run the camera at this many frames per second, clamped to what it supports, lower uses less CPU and
+    /// keeps the camera cooler
+    #[arg(long, value_name = "FPS", value_parser = parse_rate, env = "QRCAM_FPS")]
+    pub fps: Option<f64>,
+
+    /// Use a camera facing this way, by default a back camera is used if there is one
+    #[arg(long, value_enum, env = "QRCAM_FACING")]
+    pub facing: Option<Facing>,
+
+    /// Show this camera in the window, by ID or name as printed by --list-cameras. Repeat to scan from
+    /// several cameras at once, shown side by side each with its own results
+    #[arg(long = "camera", value_name = "ID|NAME", env = "QRCAM_CAMERA")]
+    pub cameras: Vec<String>,
+
+    /// Decode a video file in place of the camera, e.g. to replay a recorded scanning problem while tuning
+    /// decode options
+    #[arg(long, value_name = "PATH")]
+    pub video: Option<PathBuf>,
+
+    /// With --video, start again from the beginning at the end of the file
+    #[arg(long = "loop", requires = "video")]
+    pub loop_video: bool,
+
+    /// With --video, play at this multiple of the video's own speed
+    #[arg(long, value_name = "RATE", default_value_t = 1.0, requires = "video", value_parser = parse_rate)]
+    pub playback_rate: f64,
+
+    /// Print how long decoding took and how many codes of each format were found to stderr as JSON, on quit
+    /// or when --scan-once or --video --headless finish
+    #[arg(long)]
+    pub stats: bool,
+
+    /// With --video, print codes as they're scanned rather than showing a window, exiting at the end of the
+    /// video
+    #[arg(long, requires = "video")]
+    pub headless: bool,
+
+    /// Serve the latest frame at /frame.jpg and the codes in it at /results.json on this port, e.g. to
+    /// watch a kiosk from a browser. With several cameras the first is served
+    #[arg(long, value_name = "PORT")]
+    pub http: Option<u16>,
+
+    /// The address --http listens on, only this machine by default. Use 0.0.0.0 to watch from another
+    /// machine, anyone who can reach the port can then see the camera
+    #[arg(long, value_name = "ADDR", default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST), requires = "http")]
+    pub http_bind: IpAddr,
+}
+
+/// What `--version` prints: this crate's version, then the platform and the versions of the libraries doing
+/// the work, as locked when built, for bug reports.
+fn long_version() -> &'static str {
+    static LONG_VERSION: OnceLock<String> = OnceLock::new();
+    LONG_VERSION.get_or_init(|| {
+        let mut lines = vec![
+            env!("CARGO_PKG_VERSION").to_string(),
+            format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+        ];
+        lines.extend(env!("QRCAM_DEPENDENCY_VERSIONS").split(';').map(str::to_string));
+        lines.join("\n")
+    })
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        Ok(_) => Err("must be greater than 0".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_confirm(value: &str) -> Result<(u32, u32), String> {
+    let (frames, window) = value.split_once('/').ok_or("expected K/N, e.g. 2/3")?;
+    let frames: u32 = frames.trim().parse().map_err(|err| format!("invalid K: {}", err))?;
+    let window: u32 = window.trim().parse().map_err(|err| format!("invalid N: {}", err))?;
+    match frames {
+        0 => Err("K must be at least 1".to_string()),
+        _ if frames > window => Err("K can't be more than N".to_string()),
+        _ => Ok((frames, window)),
+    }
+}
+
+fn parse_charset(label: &str) -> Result<String, String> {
+    match encoding_rs::Encoding::for_label(label.as_bytes()) {
+        Some(_) => Ok(label.to_string()),
+        None => Err(format!("unknown character set {:?}", label)),
+    }
+}
+
+/// Start the camera, retrying with backoff as configured in the capture options.
+fn start_capture(device_info: &DeviceInfo, decoder: &Decoder, settings: &Settings) -> Result<DeviceCapture, String> {
+    let start = pin!(DeviceCapture::start_with_retries(
+        device_info,
+        decoder,
+        &settings.capture,
+        |delay| {
+            thread::sleep(delay);
+            future::ready(())
+        },
+    ));
+    // sleeping blocks rather than waiting on a timer, so there's never anything to wait for
+    match start.poll(&mut task::Context::from_waker(Waker::noop())) {
+        Poll::Ready(result) => result,
+        Poll::Pending => unreachable!("blocking retries can't be pending"),
+    }
+}
+
+pub fn list_cameras() -> ExitCode {
+    let devices = DeviceInfo::find_all();
+    if devices.is_empty() {
+        eprintln!("no cameras found");
+        return ExitCode::FAILURE;
+    }
+    for device in devices {
+        println!("{}\t{}", device.id(), device);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Text encoded in the code decoded by `self_test`.
+const SELF_TEST_TEXT: &str = "https://github.com/samuelcolvin/qrcam self test";
+
+/// Generate a QR code in memory and decode it with the configured options, to check zxing-cpp is linked
+/// and working, e.g. on a new machine, without needing a camera.
+pub fn self_test(settings: &Settings) -> ExitCode {
+    let mut options = settings.decode.clone();
+    // the configured options might only enable other formats
+    if !options.formats.contains(&CodeFormat::QrCode) {
+        options.formats.push(CodeFormat::QrCode);
+    }
+    let result = generate(SELF_TEST_TEXT, 256)
+        .context("failed to generate test code")
+        .and_then(|grey_img| Ok(decode_image(&barcode_reader(&options), &grey_img, &options)?));
+    match result {
+        Ok(qrcodes) if qrcodes.iter().any(|qrcode| qrcode.text() == SELF_TEST_TEXT) => {
+            println!("PASS");
+            ExitCode::SUCCESS
+        }
+        Ok(qrcodes) => {
+            let texts: Vec<String> = qrcodes.iter().map(|qrcode| escape_control(qrcode.text())).collect();
+            println!("FAIL: expected {:?}, decoded {:?}", SELF_TEST_TEXT, texts);
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            println!("FAIL: {:?}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+pub fn scan_once(timeout: Option<Duration>, settings: &Settings, output: Output, stats: bool) -> ExitCode {
+    match wait_for_codes(timeout, settings, stats) {
+        Ok(Some(qrcodes)) => {
+            output.begin();
+            output.print(None, &qrcodes);
+            ExitCode::SUCCESS
+        }
+        Ok(None) => {
+            eprintln!("timed out waiting for a QR code");
+            ExitCode::from(2)
+        }
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn wait_for_codes(timeout: Option<Duration>, settings: &Settings, stats: bool) -> Result<Option<Vec<QRCode>>> {
+    let device_info = DeviceInfo::find_preferred(settings.capture.facing).context("no camera found")?;
+    let decoder = Decoder::new();
+    decoder.set_rotation(settings.rotation);
+    decoder.set_flip_vertical(settings.flip_vertical);
+    decoder.set_mirror(settings.mirror);
+    decoder.set_grayscale_only(settings.grayscale_only);
+    decoder.set_options(settings.decode.clone());
+
+    let capture = start_capture(&device_info, &decoder, settings);
+    let result = capture.map_err(anyhow::Error::msg).map(|mut capture| {
+        let started = Instant::now();
+        let qrcodes = loop {
+            // only codes that have passed `--confirm`, not everything in the latest frame
+            let qrcodes = decoder.take_new_qrcodes();
+            if !qrcodes.is_empty() {
+                break Some(qrcodes);
+            }
+            if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+                break None;
+            }
+            thread::sleep(Duration::from_millis(37));
+        };
+        capture.stop();
+        qrcodes
+    });
+    decoder.shutdown();
+    if stats {
+        print_stats(&decoder);
+    }
+    result
+}
+
+/// Print the decoder's stats to stderr, keeping them apart from codes printed to stdout.
+pub fn print_stats(decoder: &Decoder) {
+    match serde_json::to_string(&decoder.stats().summary()) {
+        Ok(json) => eprintln!("{}", json),
+        Err(err) => eprintln!("Error: failed to serialize stats: {}", err),
+    }
+}
+
+/// Play a video through the decoder without a window, printing codes as they come into view until the
+/// video ends.
+pub fn decode_video(source: &VideoSource, settings: &Settings, output: Output, stats: bool) -> ExitCode {
+    let decoder = Decoder::new();
+    decoder.set_rotation(settings.rotation);
+    decoder.set_flip_vertical(settings.flip_vertical);
+    decoder.set_mirror(settings.mirror);
+    decoder.set_grayscale_only(settings.grayscale_only);
+    decoder.set_options(settings.decode.clone());
+
+    let playback = match VideoPlayback::start(source, decoder.clone()) {
+        Ok(playback) => playback,
+        Err(err) => {
+            eprintln!("Error: failed to play {}: {}", source.path.display(), err);
+            decoder.shutdown();
+            return ExitCode::FAILURE;
+        }
+    };
+    output.begin();
+    loop {
+        let finished = playback.is_finished();
+        if finished {
+            // give the decode thread time to finish with the last frame
+            thread::sleep(Duration::from_millis(100));
+        }
+        let qrcodes = decoder.take_new_qrcodes();
+        if !qrcodes.is_empty() {
+            output.print(None, &qrcodes);
+        }
+        if finished {
+            break;
+        }
+        thread::sleep(Duration::from_millis(37));
+    }
+    decoder.shutdown();
+    if stats {
+        print_stats(&decoder);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Most threads used by `decode_dir`, zxing-cpp is CPU bound so more than the core count doesn't help.
+const MAX_DECODE_THREADS: usize = 8;
+
+/// Decode every image under `dir`, failing like `decode_paths` if every one of them does. A directory
+/// with no images in it isn't a failure.
+pub fn decode_dir(dir: &Path, settings: &Settings, output: Output) -> ExitCode {
+    let mut files = Vec::new();
+    if let Err(err) = find_images(dir, &mut files) {
+        eprintln!("Error: {:?}", err);
+        return ExitCode::FAILURE;
+    }
+    files.sort();
+    match print_files(&files, settings, output) {
+        failed if failed > 0 && failed == files.len() => ExitCode::FAILURE,
+        _ => ExitCode::SUCCESS,
+    }
+}
+
+/// Decode images listed on the command line, failing only if every one of them does, e.g. because
+/// none exist, so one bad path in a batch doesn't hide the results of the rest.
+pub fn decode_paths(files: &[PathBuf], settings: &Settings, output: Output) -> ExitCode {
+    match print_files(files, settings, output) {
+        failed if failed == files.len() => ExitCode::FAILURE,
+        _ => ExitCode::SUCCESS,
+    }
+}
+
+/// Decode and print `files` in order, reporting any that can't be decoded on stderr and carrying on.
+/// Returns how many couldn't be.
+fn print_files(files: &[PathBuf], settings: &Settings, output: Output) -> usize {
+    let results = decode_files(files, &settings.decode);
+    let mut failed = 0;
+    output.begin();
+    for (file, result) in files.iter().zip(results) {
+        let qrcodes = result.unwrap_or_else(|err| {
+            eprintln!("{}: {:?}", file.display(), err);
+            failed += 1;
+            Vec::new()
+        });
+        output.print(Some(file), &qrcodes);
+    }
+    failed
+}
+
+pub fn decode_stdin(settings: &Settings, output: Output) -> ExitCode {
+    match read_stdin_image().and_then(|grey_img| {
+        let options = &settings.decode;
+        Ok(decode_image(&barcode_reader(options), &grey_img, options)?)
+    }) {
+        Ok(qrcodes) if qrcodes.is_empty() => {
+            eprintln!("no codes found");
+            ExitCode::from(2)
+        }
+        Ok(qrcodes) => {
+            output.begin();
+            output.print(None, &qrcodes);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Read an image from stdin, falling back to base64 (optionally as a `data:` URL) when the bytes
+/// aren't a recognised image format.
+fn read_stdin_image() -> Result<image::GrayImage> {
+    let mut data = Vec::new();
+    io::stdin().read_to_end(&mut data).context("failed to read stdin")?;
+    if data.is_empty() {
+        anyhow::bail!("no data on stdin");
+    }
+    if image::guess_format(&data).is_err() {
+        let text = std::str::from_utf8(&data)
+            .context("stdin is not a recognised image format")?
+            .trim();
+        let encoded = text.split_once(";base64,").map_or(text, |(_, encoded)| encoded);
+        data = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .context("stdin is not a recognised image format or base64")?;
+    }
+    let img = image::load_from_memory(&data).context("failed to decode image from stdin")?;
+    Ok(img.to_luma8())
+}
+
+fn find_images(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_images(&path, files)?;
+        } else if image::ImageFormat::from_path(&path).is_ok() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Decode files on a small pool of threads, returning results in the same order as `files`.
+fn decode_files(files: &[PathBuf], options: &DecodeOptions) -> Vec<Result<Vec<QRCode>>> {
+    let threads = thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(MAX_DECODE_THREADS)
+        .min(files.len());
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..files.len()).map(|_| None).collect::<Vec<_>>());
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let reader = barcode_reader(options);
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(file) = files.get(index) else {
+                        break;
+                    };
+                    let result = decode_file(&reader, file, options);
+                    lock(&results)[index] = Some(result);
+                }
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(anyhow::anyhow!("file not decoded"))))
+        .collect()
+}
+
+pub fn decode_file(reader: &BarcodeReader, path: &Path, options: &DecodeOptions) -> Result<Vec<QRCode>> {
+    let grey_img = image::open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?
+        .to_luma8();
+    Ok(decode_image(reader, &grey_img, options)?)
+}
@@ -1,55 +1,407 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use image::{GrayImage, Luma, Rgba, RgbaImage};
+use image::imageops::{flip_horizontal_in_place, flip_vertical_in_place, rotate180, rotate270, rotate90};
+use image::{DynamicImage, GrayImage, RgbaImage};
+use imageproc::contrast::{otsu_level, threshold, ThresholdType};
+use serde::{Deserialize, Serialize};
 use x_media::media_frame::MediaFrame;
+use zxingcpp::PointI;
 
-use crate::qr::{decode_qr, QRCode};
+use crate::camera::PixelFormat;
+use crate::qr::{barcode_reader, decode_image, decode_qr, DecodeOptions, DecodeShared, Detection, QRCode};
+use crate::sequence::Sequences;
+use crate::stats::DecodeStats;
+
+/// How long after the torch is switched off before frames are decoded again, as frames already
+/// captured with it lit are still on their way through the pipeline.
+const TORCH_SETTLE: Duration = Duration::from_millis(150);
+
+/// Clockwise rotation applied to frames, for cameras that aren't mounted upright.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    #[default]
+    None,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Rotation {
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Rotate90,
+            Self::Rotate90 => Self::Rotate180,
+            Self::Rotate180 => Self::Rotate270,
+            Self::Rotate270 => Self::None,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Rotate90,
+            2 => Self::Rotate180,
+            3 => Self::Rotate270,
+            _ => Self::None,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// Rotate a point given as fractions of the frame size the same way as the frame.
+    fn rotate_normalized(self, [x, y]: [f64; 2]) -> [f64; 2] {
+        match self {
+            Self::None => [x, y],
+            Self::Rotate90 => [1. - y, x],
+            Self::Rotate180 => [1. - x, 1. - y],
+            Self::Rotate270 => [y, 1. - x],
+        }
+    }
+}
+
+/// Which images are mirrored horizontally, both by default for a natural selfie-style view.
+///
+/// Turning off mirroring for decoding while keeping it for the preview gives positions in the camera's
+/// true orientation, e.g. for exports, while overlays are mapped onto the mirrored preview. A code on
+/// the right of the sensor is then reported on the right: every position the decoder hands out, from
+/// `on_result`, `take_qrcodes` and `take_new_qrcodes`, is in the decoded image's coordinates, only
+/// `to_display` maps them onto the preview.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mirror {
+    pub preview: bool,
+    pub decode: bool,
+}
+
+impl Default for Mirror {
+    fn default() -> Self {
+        Self {
+            preview: true,
+            decode: true,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Decoder {
     rgba_image: Arc<Mutex<Option<RgbaImage>>>,
-    grey_image: Arc<Mutex<Option<GrayImage>>>,
-    qrcodes: Arc<Mutex<Option<Vec<QRCode>>>>,
-    stop: Arc<AtomicBool>,
+    // a copy of a frame for display made on request by `next_img`, set when the next frame arrives
+    img_wanted: Arc<AtomicBool>,
+    wanted_image: Arc<Mutex<Option<RgbaImage>>>,
+    // everything the decode thread reads and writes
+    shared: DecodeShared,
+    rotation: Arc<AtomicU8>,
+    show_threshold: Arc<AtomicBool>,
+    grayscale_only: Arc<AtomicBool>,
+    flip_vertical: Arc<AtomicBool>,
+    mirror_preview: Arc<AtomicBool>,
+    mirror_decode: Arc<AtomicBool>,
+    dropped_frames: Arc<AtomicU64>,
+    // whether the torch is lit and when that last changed, only set while it's being pulsed
+    torch: Arc<Mutex<Option<(bool, Instant)>>>,
     join_handle: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
 }
 
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Decoder {
     pub fn new() -> Self {
-        let grey_image = Arc::new(Mutex::new(None));
-        let grey_image_mov = grey_image.clone();
-        let qrcodes = Arc::new(Mutex::new(None));
-        let qrcodes_mov = qrcodes.clone();
-        let stop = Arc::new(AtomicBool::new(false));
-        let stop_mov = stop.clone();
-        let join_handle = thread::spawn(move || decode_qr(grey_image_mov, qrcodes_mov, stop_mov));
-        Self {
+        let decoder = Self {
             rgba_image: Arc::new(Mutex::new(None)),
-            grey_image,
-            qrcodes,
-            stop,
-            join_handle: Arc::new(Mutex::new(Some(join_handle))),
+            img_wanted: Arc::new(AtomicBool::new(false)),
+            wanted_image: Arc::new(Mutex::new(None)),
+            shared: DecodeShared {
+                grey_image: Arc::new(Mutex::new(None)),
+                last_grey_image: Arc::new(Mutex::new(None)),
+                detections: Arc::new(Mutex::new(None)),
+                qrcodes: Arc::new(Mutex::new(None)),
+                latest_qrcodes: Arc::new(Mutex::new(Vec::new())),
+                new_qrcodes: Arc::new(Mutex::new(Vec::new())),
+                sequences: Sequences::default(),
+                stats: DecodeStats::default(),
+                callbacks: Arc::new(Mutex::new(Vec::new())),
+                options: Arc::new(Mutex::new(DecodeOptions::default())),
+                stop: Arc::new(AtomicBool::new(false)),
+                decode_paused: Arc::new(AtomicBool::new(false)),
+            },
+            rotation: Arc::new(AtomicU8::new(Rotation::None.as_u8())),
+            show_threshold: Arc::new(AtomicBool::new(false)),
+            grayscale_only: Arc::new(AtomicBool::new(false)),
+            flip_vertical: Arc::new(AtomicBool::new(false)),
+            mirror_preview: Arc::new(AtomicBool::new(true)),
+            mirror_decode: Arc::new(AtomicBool::new(true)),
+            dropped_frames: Arc::new(AtomicU64::new(0)),
+            torch: Arc::new(Mutex::new(None)),
+            join_handle: Arc::new(Mutex::new(None)),
+        };
+        *lock(&decoder.join_handle) = Some(decoder.spawn_decode_thread());
+        decoder
+    }
+
+    fn spawn_decode_thread(&self) -> thread::JoinHandle<()> {
+        let shared = self.shared.clone();
+        thread::Builder::new()
+            .name("qr-decode".to_string())
+            .spawn(move || decode_qr(shared))
+            .expect("failed to spawn decode thread")
+    }
+
+    /// Restart the decode thread if it has died, e.g. from a panic in zxing-cpp, so one bad frame
+    /// doesn't stop scanning for the rest of the session.
+    fn supervise(&self) {
+        if self.shared.stop.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut join_handle = lock(&self.join_handle);
+        if join_handle.as_ref().is_some_and(|handle| handle.is_finished()) {
+            if let Some(Err(panic)) = join_handle.take().map(thread::JoinHandle::join) {
+                log::error!("decode thread crashed, restarting it: {}", panic_message(&panic));
+            }
+            *join_handle = Some(self.spawn_decode_thread());
         }
     }
 
+    /// Register a callback invoked with the codes that have newly come into view, as an
+    /// alternative to polling `take_new_qrcodes`.
+    ///
+    /// The callback runs on the decode thread, so it should return quickly: decoding of the next
+    /// frame waits for it. It must not call `on_result` itself, which would deadlock, and a panic
+    /// in it takes down the decode thread (which is then restarted on the next frame).
+    pub fn on_result(&self, callback: impl Fn(&[QRCode]) + Send + Sync + 'static) {
+        lock(&self.shared.callbacks).push(Box::new(callback));
+    }
+
+    pub fn options(&self) -> DecodeOptions {
+        lock(&self.shared.options).clone()
+    }
+
+    pub fn set_options(&self, options: DecodeOptions) {
+        *lock(&self.shared.options) = options;
+    }
+
+    /// Decode a still image straight away on the calling thread, using the current decode options.
+    pub fn decode_gray(&self, grey_img: &GrayImage) -> Result<Vec<QRCode>, zxingcpp::Error> {
+        let options = self.options();
+        decode_image(&barcode_reader(&options), grey_img, &options)
+    }
+
+    /// The most recent frame the decode thread has finished with, `None` before the first frame.
+    pub fn last_frame(&self) -> Option<GrayImage> {
+        lock(&self.shared.last_grey_image).clone()
+    }
+
+    /// Decode the most recent frame again with different options, to see what they would find
+    /// without having to reproduce the camera conditions. `None` before the first frame.
+    pub fn redecode_last(&self, options: &DecodeOptions) -> Option<Result<Vec<QRCode>, zxingcpp::Error>> {
+        let grey_img = self.last_frame()?;
+        Some(decode_image(&barcode_reader(options), &grey_img, options))
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        Rotation::from_u8(self.rotation.load(Ordering::Relaxed))
+    }
+
+    pub fn set_rotation(&self, rotation: Rotation) {
+        self.rotation.store(rotation.as_u8(), Ordering::Relaxed);
+    }
+
+    pub fn flip_vertical(&self) -> bool {
+        self.flip_vertical.load(Ordering::Relaxed)
+    }
+
+    /// Flip frames upside down, for cameras mounted inverted. Both the displayed and decoded images
+    /// are flipped, so positions stay aligned with the preview in any combination with rotation.
+    pub fn set_flip_vertical(&self, flip: bool) {
+        self.flip_vertical.store(flip, Ordering::Relaxed);
+    }
+
+    pub fn mirror(&self) -> Mirror {
+        Mirror {
+            preview: self.mirror_preview.load(Ordering::Relaxed),
+            decode: self.mirror_decode.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Choose which of the displayed and decoded images are mirrored horizontally. When they differ,
+    /// use `to_display` to map decoded positions onto the preview.
+    pub fn set_mirror(&self, mirror: Mirror) {
+        self.mirror_preview.store(mirror.preview, Ordering::Relaxed);
+        self.mirror_decode.store(mirror.decode, Ordering::Relaxed);
+    }
+
+    /// Map a code's position from the decoded image to the displayed one, which differ when only one of
+    /// them is mirrored.
+    pub fn to_display(&self, qrcode: QRCode) -> QRCode {
+        let mirror = self.mirror();
+        if mirror.preview == mirror.decode {
+            return qrcode;
+        }
+        let (width, height) = qrcode.frame_size();
+        match self.rotation() {
+            // images are mirrored before they're rotated, so a quarter turn makes it a vertical flip
+            Rotation::Rotate90 | Rotation::Rotate270 => qrcode.map_position(|point| PointI {
+                x: point.x,
+                y: height as i32 - 1 - point.y,
+            }),
+            Rotation::None | Rotation::Rotate180 => qrcode.map_position(|point| PointI {
+                x: width as i32 - 1 - point.x,
+                y: point.y,
+            }),
+        }
+    }
+
+    pub fn show_threshold(&self) -> bool {
+        self.show_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Display the grey image binarized with Otsu's method instead of the colour frame, roughly how
+    /// the decoder sees it, to help diagnose glare and contrast problems.
+    pub fn set_show_threshold(&self, show: bool) {
+        self.show_threshold.store(show, Ordering::Relaxed);
+    }
+
+    pub fn decode_paused(&self) -> bool {
+        self.shared.decode_paused.load(Ordering::Relaxed)
+    }
+
+    /// Stop decoding frames while the camera and preview keep running, e.g. to save CPU while aiming.
+    pub fn set_decode_paused(&self, paused: bool) {
+        self.shared.decode_paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn grayscale_only(&self) -> bool {
+        self.grayscale_only.load(Ordering::Relaxed)
+    }
+
+    /// Only extract the luma of each frame and show it as a grey preview, skipping the colour conversion
+    /// to save CPU.
+    pub fn set_grayscale_only(&self, grayscale_only: bool) {
+        self.grayscale_only.store(grayscale_only, Ordering::Relaxed);
+    }
+
+    /// Note the torch being switched on or off while it's pulsed. Frames that may have been taken with
+    /// it lit, while it is and for `TORCH_SETTLE` after, are shown but not decoded.
+    pub fn set_torch_lit(&self, lit: bool) {
+        *lock(&self.torch) = Some((lit, Instant::now()));
+    }
+
+    fn torch_glare(&self) -> bool {
+        lock(&self.torch).is_some_and(|(lit, changed)| lit || changed.elapsed() < TORCH_SETTLE)
+    }
+
+    /// Count a frame the capture session dropped, e.g. because the previous one was still being converted.
+    pub fn record_dropped_frame(&self) {
+        self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many frames the capture session has dropped since the decoder was created.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    /// Stop the decode thread and wait for it to exit, calling this more than once is a no-op.
     pub fn shutdown(&self) {
-        self.stop.store(true, Ordering::Relaxed);
-        if let Some(handle) = self.join_handle.lock().ok().and_then(|mut h| h.take()) {
-            handle.join().unwrap();
+        self.shared.stop.store(true, Ordering::Relaxed);
+        let handle = lock(&self.join_handle).take();
+        if let Some(Err(panic)) = handle.map(thread::JoinHandle::join) {
+            log::error!("decode thread crashed: {}", panic_message(&panic));
         }
     }
 
+    /// The newest frame for display, leaving nothing to take until the next frame arrives.
     pub fn take_img(&self) -> Option<RgbaImage> {
-        self.rgba_image.lock().ok().and_then(|mut img| img.take())
+        lock(&self.rgba_image).take()
+    }
+
+    /// A copy of the newest frame without taking it, so another reader (e.g. saving a frame) can grab it
+    /// without racing the preview for it. `None` before the first frame or once the preview has taken
+    /// the newest one, until the next arrives.
+    ///
+    /// This clones the whole image, around 8MB for a 1080p frame, so call it when a frame is needed
+    /// rather than on every frame.
+    pub fn peek_img(&self) -> Option<RgbaImage> {
+        lock(&self.rgba_image).clone()
+    }
+
+    /// A copy of the newest frame for readers other than the preview, which usually takes frames before
+    /// `peek_img` can see them: waits up to `timeout` for the next frame to be copied if there's none to
+    /// peek, falling back to the last frame copied this way.
+    pub fn next_img(&self, timeout: Duration) -> Option<RgbaImage> {
+        if let Some(img) = self.peek_img() {
+            return Some(img);
+        }
+        self.img_wanted.store(true, Ordering::Relaxed);
+        let started = Instant::now();
+        while self.img_wanted.load(Ordering::Relaxed) && started.elapsed() < timeout {
+            thread::sleep(Duration::from_millis(5));
+        }
+        lock(&self.wanted_image).clone()
     }
 
     pub fn take_qrcodes(&self) -> Option<Vec<QRCode>> {
-        self.qrcodes.lock().ok().and_then(|mut qrcodes| qrcodes.take())
+        lock(&self.shared.qrcodes).take()
+    }
+
+    /// The codes in the most recently decoded frame, without taking them from `take_qrcodes`.
+    pub fn latest_qrcodes(&self) -> Vec<QRCode> {
+        lock(&self.shared.latest_qrcodes).clone()
+    }
+
+    /// Decode timings and formats found so far.
+    pub fn stats(&self) -> &DecodeStats {
+        &self.shared.stats
+    }
+
+    /// Structured Append sequences with parts still to scan, as (parts scanned, total parts).
+    pub fn sequence_progress(&self) -> Vec<(usize, usize)> {
+        self.shared.sequences.progress()
+    }
+
+    /// Codes that have come into view since the last call, each reported once while it stays in frame.
+    ///
+    /// Parts of Structured Append sequences are held back, the combined code is reported once the last
+    /// part has been scanned.
+    pub fn take_new_qrcodes(&self) -> Vec<QRCode> {
+        std::mem::take(&mut *lock(&self.shared.new_qrcodes))
+    }
+
+    /// Queue codes found by the camera's own detector for the decode thread to report with the next
+    /// frame. Their corners are mirrored, flipped and rotated the same way as the decoded image, so
+    /// they're positioned like codes zxing-cpp finds in it.
+    pub fn record_detections(&self, detections: Vec<Detection>) {
+        let rotation = self.rotation();
+        let flip_vertical = self.flip_vertical();
+        let mirror = self.mirror().decode;
+        let detections = detections
+            .into_iter()
+            .map(|mut detection| {
+                detection.corners = detection.corners.map(|[x, y]| {
+                    let x = if mirror { 1. - x } else { x };
+                    let y = if flip_vertical { 1. - y } else { y };
+                    rotation.rotate_normalized([x, y])
+                });
+                detection
+            })
+            .collect();
+        *lock(&self.shared.detections) = Some(detections);
     }
 
-    pub fn decode(&self, frame: MediaFrame) {
+    /// Convert a captured frame for display and queue it for decoding.
+    ///
+    /// `timestamp` is the frame's presentation time in seconds on the capture clock, which is
+    /// carried through to the codes decoded from it. `pixel_format` tells packed formats apart, single
+    /// plane frames in an unknown format are taken as UYVY.
+    pub fn decode(&self, frame: MediaFrame, pixel_format: Option<PixelFormat>, timestamp: Option<f64>) {
+        self.supervise();
         // println!("frame desc: {:?}", frame.description());
 
         let Ok(mapped_guard) = frame.map() else {
@@ -58,68 +410,576 @@ impl Decoder {
         let Some(planes) = mapped_guard.planes() else {
             return;
         };
-        for plane in planes {
-            match (plane.stride(), plane.height(), plane.data()) {
-                (Some(stride), Some(height), Some(data)) => self.record_img(stride, height, data),
-                _ => (),
+        let planes: Vec<(u32, u32, &[u8])> = planes
+            .into_iter()
+            .filter_map(|plane| Some((plane.stride()?, plane.height()?, plane.data()?)))
+            .collect();
+        self.decode_planes(&planes, pixel_format, timestamp);
+    }
+
+    /// Convert and queue a frame's planes, each as (stride, height, data).
+    fn decode_planes(&self, planes: &[(u32, u32, &[u8])], pixel_format: Option<PixelFormat>, timestamp: Option<f64>) {
+        // frames with empty planes turn up while the device is switching formats
+        if planes
+            .iter()
+            .any(|&(stride, height, data)| stride < 2 || height == 0 || data.is_empty())
+        {
+            log::trace!("skipping frame with an empty plane");
+            return;
+        }
+        let flip_vertical = self.flip_vertical();
+        let bgra = pixel_format == Some(PixelFormat::Bgra);
+        if self.grayscale_only() {
+            let grey_img = match planes {
+                &[(stride, height, data)] if bgra => bgra_luma(stride, height, data, flip_vertical),
+                &[(stride, height, data)] => uyvy_luma(stride, height, data, flip_vertical),
+                // the first plane of planar formats is luma
+                &[(luma_stride, height, luma), ..] => planar_luma(luma_stride, height, luma, flip_vertical),
+                [] => return,
             };
+            self.record_img(None, grey_img, timestamp);
+            return;
         }
+        let (rgba_img, grey_img) = match planes {
+            // packed formats have a single plane
+            &[(stride, height, data)] if bgra => convert_bgra(stride, height, data, flip_vertical),
+            &[(stride, height, data)] => convert_uyvy(stride, height, data, flip_vertical),
+            // planar formats have luma first, then either interleaved chroma (NV12) or separate Cb and Cr (I420)
+            &[(luma_stride, height, luma), (chroma_stride, _, chroma)] => {
+                convert_planar(luma_stride, height, luma, flip_vertical, |x, y| {
+                    let index = y * chroma_stride as usize + x * 2;
+                    match chroma.get(index..index + 2) {
+                        Some(&[u, v]) => (u, v),
+                        _ => (128, 128),
+                    }
+                })
+            }
+            &[(luma_stride, height, luma), (cb_stride, _, cb), (cr_stride, _, cr)] => {
+                convert_planar(luma_stride, height, luma, flip_vertical, |x, y| {
+                    let u = cb.get(y * cb_stride as usize + x).copied().unwrap_or(128);
+                    let v = cr.get(y * cr_stride as usize + x).copied().unwrap_or(128);
+                    (u, v)
+                })
+            }
+            planes => {
+                log::trace!("skipping frame with {} planes", planes.len());
+                return;
+            }
+        };
+        self.record_img(Some(rgba_img), grey_img, timestamp);
     }
 
-    fn record_img(&self, stride: u32, height: u32, data: &[u8]) {
-        // For YUV422 format, the actual number of pixels is half the stride width
-        let width = stride / 2;
-        let mut rgba_img = RgbaImage::new(width, height);
-        let mut grey_img = GrayImage::new(width, height);
-
-        for row in 0..height {
-            for x in 0..width / 2 {
-                // flip the image horizontally
-                let x_reverse = width - x - 1;
-                // Each 4 bytes represent 2 pixels in UYVY format
-                let idx = (row * stride + x_reverse * 4) as usize;
-
-                // Safety check to avoid out of bounds access
-                if idx + 3 >= data.len() {
-                    continue;
+    /// Orient a converted frame and queue it, without a colour image the preview is made from the grey one.
+    fn record_img(&self, mut rgba_img: Option<RgbaImage>, mut grey_img: GrayImage, timestamp: Option<f64>) {
+        // frames are converted mirrored, flip back whichever image shouldn't be before rotating, so the
+        // mirror is always across the camera's vertical axis
+        let mirror = self.mirror();
+        if let (false, Some(rgba_img)) = (mirror.preview, rgba_img.as_mut()) {
+            flip_horizontal_in_place(rgba_img);
+        }
+        if !mirror.decode {
+            flip_horizontal_in_place(&mut grey_img);
+        }
+        // both buffers get the same rotation, so unless only one is mirrored positions found in the grey
+        // image are already in the coordinate space of the displayed image
+        let rotation = self.rotation();
+        let (rgba_img, grey_img) = match rotation {
+            Rotation::None => (rgba_img, grey_img),
+            Rotation::Rotate90 => (rgba_img.map(|img| rotate90(&img)), rotate90(&grey_img)),
+            Rotation::Rotate180 => (rgba_img.map(|img| rotate180(&img)), rotate180(&grey_img)),
+            Rotation::Rotate270 => (rgba_img.map(|img| rotate270(&img)), rotate270(&grey_img)),
+        };
+        let show_threshold = self.show_threshold();
+        let rgba_img = match rgba_img {
+            Some(rgba_img) if !show_threshold => rgba_img,
+            _ => {
+                let mut preview = match show_threshold {
+                    true => threshold(&grey_img, otsu_level(&grey_img), ThresholdType::Binary),
+                    false => grey_img.clone(),
+                };
+                // shown in place of the preview, so mirrored like it for overlays to line up
+                if mirror.preview != mirror.decode {
+                    match rotation {
+                        Rotation::Rotate90 | Rotation::Rotate270 => flip_vertical_in_place(&mut preview),
+                        Rotation::None | Rotation::Rotate180 => flip_horizontal_in_place(&mut preview),
+                    }
                 }
+                DynamicImage::ImageLuma8(preview).to_rgba8()
+            }
+        };
+        if self.img_wanted.swap(false, Ordering::Relaxed) {
+            *lock(&self.wanted_image) = Some(rgba_img.clone());
+        }
+        *lock(&self.rgba_image) = Some(rgba_img);
+        if !self.torch_glare() {
+            *lock(&self.shared.grey_image) = Some((grey_img, timestamp));
+        }
+    }
+}
 
-                // Extract UYVY values - note because the image is flipped horizontally
-                // we select items in this order, not u, y0, v, y1
-                let v = data[idx];
-                let y1 = data[idx + 1];
-                let u = data[idx + 2];
-                let y0 = data[idx + 3];
+/// Lock a mutex, carrying on with the data if another thread panicked while holding it, the slots
+/// here are only ever overwritten whole so they can't be left half-updated.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| {
+        log::warn!("recovering poisoned mutex");
+        mutex.clear_poison();
+        err.into_inner()
+    })
+}
 
-                // Convert to RGB
-                let rgb0 = yuv_to_rgb(y0 as f32, u as f32, v as f32);
-                let rgb1 = yuv_to_rgb(y1 as f32, u as f32, v as f32);
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
 
-                // Place both pixels in the output image
-                rgba_img.put_pixel(x * 2, row, Rgba([rgb0[0], rgb0[1], rgb0[2], 255]));
-                rgba_img.put_pixel(x * 2 + 1, row, Rgba([rgb1[0], rgb1[1], rgb1[2], 255]));
+/// Convert a packed UYVY plane into an RGBA image for display and a grey image for decoding.
+///
+/// Works a row at a time on raw buffers with fixed-point maths, computing the chroma terms once per
+/// pixel pair rather than per pixel.
+pub fn convert_uyvy(stride: u32, height: u32, data: &[u8], flip_vertical: bool) -> (RgbaImage, GrayImage) {
+    // For YUV422 format, the actual number of pixels is half the stride width
+    let width = stride / 2;
+    if width == 0 || height == 0 {
+        // nothing to convert, and splitting the buffers into zero-width rows would panic
+        return (RgbaImage::new(width, height), GrayImage::new(width, height));
+    }
+    let pairs = (width / 2) as usize;
+    let mut rgba = vec![0u8; (width * height * 4) as usize];
+    let mut grey = vec![0u8; (width * height) as usize];
 
-                grey_img.put_pixel(x * 2, row, Luma([y0]));
-                grey_img.put_pixel(x * 2 + 1, row, Luma([y1]));
-            }
+    let rows = rgba
+        .chunks_exact_mut(width as usize * 4)
+        .zip(grey.chunks_exact_mut(width as usize));
+    for (row, (rgba_row, grey_row)) in rows.enumerate() {
+        let src_row = if flip_vertical { height as usize - 1 - row } else { row };
+        let start = (src_row * stride as usize).min(data.len());
+        // Safety check to avoid out of bounds access, a truncated final row is converted as far as it goes
+        let src_row = &data[start..(start + pairs * 4).min(data.len())];
+        // pairs missing from the end of a truncated row end up at the start once flipped
+        let missing = pairs - src_row.len() / 4;
+        // flip the image horizontally by walking the source pairs backwards,
+        // each 4 bytes represent 2 pixels in UYVY format
+        let src_pairs = src_row.chunks_exact(4).rev();
+        let dst_pairs = rgba_row
+            .chunks_exact_mut(8)
+            .zip(grey_row.chunks_exact_mut(2))
+            .skip(missing);
+        for (src, (rgba_out, grey_out)) in src_pairs.zip(dst_pairs) {
+            // because the pair is flipped we select items in this order, not u, y0, v, y1
+            let (v, y1, u, y0) = (src[0], src[1], src[2], src[3]);
+            let chroma = Chroma::new(u, v);
+            rgba_out[..4].copy_from_slice(&chroma.rgba(y0));
+            rgba_out[4..].copy_from_slice(&chroma.rgba(y1));
+            grey_out[0] = y0;
+            grey_out[1] = y1;
+        }
+        // an odd trailing column has no pair of its own, repeat its neighbour so the frame is
+        // filled edge to edge rather than leaving a black stripe
+        if width % 2 == 1 && width > 1 {
+            let last = width as usize - 1;
+            rgba_row.copy_within((last - 1) * 4..last * 4, last * 4);
+            grey_row[last] = grey_row[last - 1];
+        }
+    }
+    (
+        RgbaImage::from_raw(width, height, rgba).expect("buffer sized to match dimensions"),
+        GrayImage::from_raw(width, height, grey).expect("buffer sized to match dimensions"),
+    )
+}
+
+/// Convert a packed BGRA plane into an RGBA image for display and a grey image for decoding, mirrored
+/// like `convert_uyvy`.
+///
+/// Grey is computed with the BT.601 luma weights, so it matches what the camera would have produced as Y.
+pub fn convert_bgra(stride: u32, height: u32, data: &[u8], flip_vertical: bool) -> (RgbaImage, GrayImage) {
+    let width = stride / 4;
+    let mut rgba = RgbaImage::new(width, height);
+    let mut grey = GrayImage::new(width, height);
+    if width == 0 {
+        return (rgba, grey);
+    }
+    let rows = rgba
+        .chunks_exact_mut(width as usize * 4)
+        .zip(grey.chunks_exact_mut(width as usize));
+    for (row, (rgba_row, grey_row)) in rows.enumerate() {
+        let src_row = if flip_vertical { height as usize - 1 - row } else { row };
+        let start = (src_row * stride as usize).min(data.len());
+        let src = &data[start..(start + width as usize * 4).min(data.len())];
+        // written from the end of the row to mirror it
+        let dst = rgba_row.chunks_exact_mut(4).rev().zip(grey_row.iter_mut().rev());
+        for (pixel, (rgba_out, grey_out)) in src.chunks_exact(4).zip(dst) {
+            let (b, g, r) = (pixel[0], pixel[1], pixel[2]);
+            rgba_out.copy_from_slice(&[r, g, b, 255]);
+            *grey_out = bt601_luma(r, g, b);
+        }
+    }
+    (rgba, grey)
+}
+
+/// Just the luma of a BGRA frame, mirrored and flipped like `convert_bgra`.
+fn bgra_luma(stride: u32, height: u32, data: &[u8], flip_vertical: bool) -> GrayImage {
+    let width = stride as usize / 4;
+    let mut grey = GrayImage::new(width as u32, height);
+    if width == 0 {
+        return grey;
+    }
+    for (row, grey_row) in grey.chunks_exact_mut(width).enumerate() {
+        let src_row = if flip_vertical { height as usize - 1 - row } else { row };
+        let start = (src_row * stride as usize).min(data.len());
+        let src = &data[start..(start + width * 4).min(data.len())];
+        for (grey_out, pixel) in grey_row.iter_mut().rev().zip(src.chunks_exact(4)) {
+            *grey_out = bt601_luma(pixel[2], pixel[1], pixel[0]);
         }
-        if let Ok(mut image) = self.rgba_image.lock() {
-            *image = Some(rgba_img);
+    }
+    grey
+}
+
+/// Fixed-point BT.601 luma, the weights sum to 256 so white stays 255.
+fn bt601_luma(r: u8, g: u8, b: u8) -> u8 {
+    ((77 * r as u32 + 150 * g as u32 + 29 * b as u32) >> 8) as u8
+}
+
+/// Just the luma of a UYVY frame, mirrored and flipped like `convert_uyvy`.
+fn uyvy_luma(stride: u32, height: u32, data: &[u8], flip_vertical: bool) -> GrayImage {
+    let width = stride as usize / 2;
+    let mut grey = GrayImage::new(width as u32, height);
+    if width == 0 {
+        return grey;
+    }
+    for (row, grey_row) in grey.chunks_exact_mut(width).enumerate() {
+        let src_row = if flip_vertical { height as usize - 1 - row } else { row };
+        let start = (src_row * stride as usize).min(data.len());
+        let src = &data[start..(start + width * 2).min(data.len())];
+        // luma is every second byte, written from the end of the row to mirror it
+        for (grey_out, &y) in grey_row.iter_mut().rev().zip(src.iter().skip(1).step_by(2)) {
+            *grey_out = y;
+        }
+    }
+    grey
+}
+
+/// Just the luma plane of a planar frame, mirrored and flipped like `convert_planar`.
+fn planar_luma(luma_stride: u32, height: u32, luma: &[u8], flip_vertical: bool) -> GrayImage {
+    let width = luma_stride as usize;
+    let mut grey = GrayImage::new(width as u32, height);
+    if width == 0 {
+        return grey;
+    }
+    for (row, grey_row) in grey.chunks_exact_mut(width).enumerate() {
+        let src_row = if flip_vertical { height as usize - 1 - row } else { row };
+        let start = (src_row * width).min(luma.len());
+        let src = &luma[start..(start + width).min(luma.len())];
+        for (grey_out, &y) in grey_row.iter_mut().rev().zip(src) {
+            *grey_out = y;
         }
-        if let Ok(mut grey_image) = self.grey_image.lock() {
-            *grey_image = Some(grey_img);
+    }
+    grey
+}
+
+/// Convert a planar 4:2:0 frame into an RGBA image for display and a grey image for decoding,
+/// mirrored like `convert_uyvy`.
+///
+/// The grey image comes straight from the luma plane, `chroma_at` gives the (Cb, Cr) sample for a
+/// chroma column and row and is only used for the RGBA preview. The luma stride is taken as the
+/// width as frames don't carry their width separately, so any row padding shows as a stripe.
+fn convert_planar(
+    luma_stride: u32,
+    height: u32,
+    luma: &[u8],
+    flip_vertical: bool,
+    chroma_at: impl Fn(usize, usize) -> (u8, u8),
+) -> (RgbaImage, GrayImage) {
+    let width = luma_stride as usize;
+    let mut rgba = vec![0u8; width * height as usize * 4];
+    let mut grey = vec![0u8; width * height as usize];
+
+    let rows = rgba.chunks_exact_mut(width * 4).zip(grey.chunks_exact_mut(width));
+    for (row, (rgba_row, grey_row)) in rows.enumerate() {
+        let src_row = if flip_vertical { height as usize - 1 - row } else { row };
+        let start = (src_row * width).min(luma.len());
+        let src = &luma[start..(start + width).min(luma.len())];
+        // walk the luma row backwards so the image is mirrored the same way as UYVY frames
+        let dst = rgba_row.chunks_exact_mut(4).zip(grey_row.iter_mut()).rev();
+        for (x, (&y, (rgba_out, grey_out))) in src.iter().zip(dst).enumerate() {
+            let (u, v) = chroma_at(x / 2, src_row / 2);
+            rgba_out.copy_from_slice(&Chroma::new(u, v).rgba(y));
+            *grey_out = y;
         }
     }
+    (
+        RgbaImage::from_raw(width as u32, height, rgba).expect("buffer sized to match dimensions"),
+        GrayImage::from_raw(width as u32, height, grey).expect("buffer sized to match dimensions"),
+    )
+}
+
+/// Chroma contribution to each RGB channel, in 16.16 fixed point.
+struct Chroma {
+    r: i32,
+    g: i32,
+    b: i32,
 }
 
-fn yuv_to_rgb(y: f32, u: f32, v: f32) -> [u8; 3] {
-    let r = y + (1.402 * (v - 128.));
-    let g = y - (0.344136 * (u - 128.)) - (0.714136 * (v - 128.));
-    let b = y + (1.772 * (u - 128.));
+impl Chroma {
+    // the JPEG/BT.601 full-range coefficients scaled by 2^16
+    const R_V: i32 = 91_881; // 1.402
+    const G_U: i32 = 22_554; // 0.344136
+    const G_V: i32 = 46_802; // 0.714136
+    const B_U: i32 = 116_130; // 1.772
+
+    fn new(u: u8, v: u8) -> Self {
+        let u = u as i32 - 128;
+        let v = v as i32 - 128;
+        Self {
+            r: Self::R_V * v,
+            g: -Self::G_U * u - Self::G_V * v,
+            b: Self::B_U * u,
+        }
+    }
+
+    fn rgba(&self, y: u8) -> [u8; 4] {
+        // add a half before shifting so this rounds the same way as the float version did
+        let y = ((y as i32) << 16) + (1 << 15);
+        [clamp(y + self.r), clamp(y + self.g), clamp(y + self.b), 255]
+    }
+}
 
-    [clamp(r), clamp(g), clamp(b)]
+fn clamp(value: i32) -> u8 {
+    (value >> 16).clamp(0, 255) as u8
 }
 
-fn clamp(value: f32) -> u8 {
-    value.round().clamp(0.0, 255.0) as u8
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_is_prompt() {
+        let decoder = Decoder::new();
+        let started = Instant::now();
+        decoder.shutdown();
+        assert!(
+            started.elapsed() < Duration::from_secs(1),
+            "took {:?}",
+            started.elapsed()
+        );
+        assert!(lock(&decoder.join_handle).is_none());
+    }
+
+    #[test]
+    fn shutdown_twice() {
+        let decoder = Decoder::new();
+        decoder.shutdown();
+        let started = Instant::now();
+        decoder.shutdown();
+        assert!(
+            started.elapsed() < Duration::from_millis(10),
+            "took {:?}",
+            started.elapsed()
+        );
+        // a stopped decoder isn't restarted by the next frame either
+        decoder.supervise();
+        assert!(lock(&decoder.join_handle).is_none());
+    }
+
+    /// The floating point conversion `convert_uyvy` used before it moved to fixed point.
+    fn float_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+        let (y, u, v) = (y as f32, u as f32 - 128., v as f32 - 128.);
+        let r = y + 1.402 * v;
+        let g = y - 0.344136 * u - 0.714136 * v;
+        let b = y + 1.772 * u;
+        [r, g, b].map(|value| value.round().clamp(0., 255.) as u8)
+    }
+
+    /// A UYVY frame of pseudo-random bytes, so every part of the colour space turns up somewhere.
+    fn noise_uyvy(width: u32, height: u32) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0..width * height * 2)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    fn assert_matches_float(y: u8, u: u8, v: u8) {
+        let fixed = Chroma::new(u, v).rgba(y);
+        let float = float_rgb(y, u, v);
+        assert!(
+            fixed
+                .iter()
+                .zip(float)
+                .all(|(&fixed, float)| fixed.abs_diff(float) <= 1),
+            "y={} u={} v={}: {:?} vs {:?}",
+            y,
+            u,
+            v,
+            fixed,
+            float
+        );
+    }
+
+    #[test]
+    fn fixed_point_matches_float() {
+        // every third chroma value, 0 and 255 included, keeps this quick in debug builds
+        for y in 0..=255 {
+            for u in (0..=255).step_by(3) {
+                for v in (0..=255).step_by(3) {
+                    assert_matches_float(y, u, v);
+                }
+            }
+        }
+    }
+
+    /// All 16.7 million combinations, run with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn fixed_point_matches_float_exhaustive() {
+        for y in 0..=255 {
+            for u in 0..=255 {
+                for v in 0..=255 {
+                    assert_matches_float(y, u, v);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn convert_uyvy_matches_float() {
+        let (width, height) = (64, 48);
+        let data = noise_uyvy(width, height);
+        let (rgba, grey) = convert_uyvy(width * 2, height, &data, false);
+        for row in 0..height {
+            for pair in 0..width / 2 {
+                // read the way the float version did, pairs walked backwards from the end of the row
+                let idx = (row * width * 2 + pair * 4) as usize;
+                let (v, y1, u, y0) = (data[idx], data[idx + 1], data[idx + 2], data[idx + 3]);
+                let x = width - pair * 2 - 2;
+                for (x, y) in [(x, y0), (x + 1, y1)] {
+                    assert_eq!(grey.get_pixel(x, row)[0], y);
+                    let fixed = rgba.get_pixel(x, row).0;
+                    assert_eq!(fixed[3], 255);
+                    for (fixed, float) in fixed.into_iter().zip(float_rgb(y, u, v)) {
+                        assert!(fixed.abs_diff(float) <= 1, "pixel ({}, {})", x, row);
+                    }
+                }
+            }
+        }
+    }
+
+    fn assert_filled(rgba: &RgbaImage, grey: &GrayImage) {
+        for (x, y, pixel) in grey.enumerate_pixels() {
+            assert_eq!(pixel[0], 200, "grey pixel ({}, {})", x, y);
+        }
+        for (x, y, pixel) in rgba.enumerate_pixels() {
+            assert_eq!(pixel.0, [200, 200, 200, 255], "rgba pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn convert_uyvy_fills_columns() {
+        // 5 pixels wide, so the last column has no pair of its own
+        for stride in [8, 10] {
+            let data = [128, 200].repeat(stride / 2 * 3);
+            let (rgba, grey) = convert_uyvy(stride as u32, 3, &data, false);
+            assert_eq!(grey.dimensions(), (stride as u32 / 2, 3));
+            assert_filled(&rgba, &grey);
+        }
+    }
+
+    #[test]
+    fn convert_planar_fills_columns() {
+        for width in [4, 5] {
+            let luma = vec![200; width * 3];
+            let (rgba, grey) = convert_planar(width as u32, 3, &luma, false, |_, _| (128, 128));
+            assert_eq!(grey.dimensions(), (width as u32, 3));
+            assert_filled(&rgba, &grey);
+        }
+    }
+
+    #[test]
+    fn empty_planes_skipped() {
+        let decoder = Decoder::new();
+        decoder.shutdown();
+        let data = [128u8; 64];
+        for planes in [
+            vec![(0, 4, &data[..])],
+            vec![(16, 0, &data[..])],
+            vec![(16, 4, &data[..0])],
+            vec![(8, 4, &data[..32]), (0, 2, &data[..])],
+        ] {
+            decoder.decode_planes(&planes, None, None);
+            assert!(decoder.take_img().is_none(), "{:?}", planes);
+            assert!(lock(&decoder.shared.grey_image).is_none(), "{:?}", planes);
+        }
+        for grayscale_only in [false, true] {
+            decoder.set_grayscale_only(grayscale_only);
+            decoder.decode_planes(&[(16, 4, &data[..])], None, None);
+            assert!(lock(&decoder.shared.grey_image).take().is_some());
+        }
+    }
+
+    #[test]
+    fn unmirrored_decode_keeps_sensor_positions() {
+        let decoder = Decoder::new();
+        decoder.shutdown();
+        decoder.set_mirror(Mirror {
+            preview: true,
+            decode: false,
+        });
+        // a code in the right half of the sensor, as a UYVY frame in sensor order with neutral chroma
+        let code = crate::generate::generate("right half", 100).unwrap();
+        let (width, height) = (code.width() * 2 + 40, code.height() + 40);
+        let left = width - code.width() - 20;
+        let mut data = [128, 255].repeat((width * height) as usize);
+        for (x, y, pixel) in code.enumerate_pixels() {
+            data[((y + 20) * width * 2 + (left + x) * 2 + 1) as usize] = pixel[0];
+        }
+        decoder.decode_planes(&[(width * 2, height, &data[..])], None, None);
+
+        let (grey_img, _) = lock(&decoder.shared.grey_image).take().unwrap();
+        let qrcodes = decoder.decode_gray(&grey_img).unwrap();
+        assert_eq!(qrcodes.len(), 1);
+        for [x, _] in qrcodes[0].corners() {
+            assert!(x > width as i32 / 2, "{:?}", qrcodes[0].corners());
+        }
+        // and on the mirrored preview it's on the left
+        let displayed = decoder.to_display(qrcodes[0].clone());
+        for [x, _] in displayed.corners() {
+            assert!(x < width as i32 / 2, "{:?}", displayed.corners());
+        }
+    }
+
+    #[test]
+    fn to_display_mirrors_positions() {
+        let decoder = Decoder::new();
+        decoder.shutdown();
+        let detection = Detection {
+            text: "mirrored".to_string(),
+            format: crate::qr::CodeFormat::QrCode,
+            corners: [[0.1, 0.2], [0.3, 0.2], [0.3, 0.4], [0.1, 0.4]],
+        };
+        let qrcode = QRCode::from_detection(&detection, (100, 80));
+        assert_eq!(qrcode.corners(), [[10, 16], [30, 16], [30, 32], [10, 32]]);
+
+        // both mirrored, or neither, leaves positions alone
+        assert_eq!(decoder.to_display(qrcode.clone()).corners(), qrcode.corners());
+
+        decoder.set_mirror(Mirror {
+            preview: true,
+            decode: false,
+        });
+        assert_eq!(
+            decoder.to_display(qrcode.clone()).corners(),
+            [[89, 16], [69, 16], [69, 32], [89, 32]]
+        );
+        decoder.set_rotation(Rotation::Rotate90);
+        assert_eq!(
+            decoder.to_display(qrcode.clone()).corners(),
+            [[10, 63], [30, 63], [30, 47], [10, 47]]
+        );
+    }
 }
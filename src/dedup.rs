@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::qr::QRCode;
+
+/// Default for how long after a code was last decoded before it's considered to have left the frame,
+/// see `DecodeOptions::gone_after_ms`.
+pub const GONE_AFTER_MS: u64 = 1500;
+
+/// Tracks which codes are currently in view so that a code held steady in front of the camera
+/// is reported as new once, not on every frame. A code counts as in view until it hasn't been decoded
+/// for `gone_after`, so one missed for a few frames isn't reported again when it's next decoded.
+///
+/// A cooldown additionally stops a code being reported again too soon after it last was, e.g. when
+/// it flickers in and out of view, so automatic actions on new codes fire at most once per window.
+///
+/// Codes can also be required to be confirmed, decoded in K of the last N frames, before they count as
+/// in view at all, so noise misread as a code for a single frame is never reported.
+#[derive(Debug)]
+pub struct Dedup {
+    last_seen: HashMap<String, Instant>,
+    last_reported: HashMap<String, Instant>,
+    gone_after: Duration,
+    cooldown: Duration,
+    // texts decoded in each of the last `confirm_window` frames, newest last
+    recent: VecDeque<HashSet<String>>,
+    confirm_frames: usize,
+    confirm_window: usize,
+}
+
+impl Default for Dedup {
+    fn default() -> Self {
+        Self::new(Duration::ZERO)
+    }
+}
+
+impl Dedup {
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            last_seen: HashMap::new(),
+            last_reported: HashMap::new(),
+            gone_after: Duration::from_millis(GONE_AFTER_MS),
+            cooldown,
+            recent: VecDeque::new(),
+            confirm_frames: 1,
+            confirm_window: 1,
+        }
+    }
+
+    pub fn set_cooldown(&mut self, cooldown: Duration) {
+        self.cooldown = cooldown;
+    }
+
+    pub fn set_gone_after(&mut self, gone_after: Duration) {
+        self.gone_after = gone_after;
+    }
+
+    /// Require codes to be decoded in `frames` of the last `window` frames before they're reported,
+    /// `window` is raised to `frames` if it's smaller.
+    pub fn set_confirmation(&mut self, frames: u32, window: u32) {
+        self.confirm_frames = frames.max(1) as usize;
+        self.confirm_window = (window as usize).max(self.confirm_frames);
+        while self.recent.len() > self.confirm_window {
+            self.recent.pop_front();
+        }
+    }
+
+    fn is_confirmed(&self, text: &str) -> bool {
+        self.recent.iter().filter(|frame| frame.contains(text)).count() >= self.confirm_frames
+    }
+
+    /// Record the codes decoded from a frame, returning those that weren't already in view and
+    /// haven't been reported within the cooldown.
+    pub fn update(&mut self, qrcodes: &[QRCode]) -> Vec<QRCode> {
+        let now = Instant::now();
+        let gone_after = self.gone_after;
+        self.last_seen
+            .retain(|_, last_seen| now.duration_since(*last_seen) < gone_after);
+        let cooldown = self.cooldown;
+        self.last_reported
+            .retain(|_, last_reported| now.duration_since(*last_reported) < cooldown);
+
+        self.recent
+            .push_back(qrcodes.iter().map(|qrcode| qrcode.text().to_string()).collect());
+        while self.recent.len() > self.confirm_window {
+            self.recent.pop_front();
+        }
+
+        let mut new_qrcodes = Vec::new();
+        for qrcode in qrcodes {
+            let text = qrcode.text();
+            if !self.is_confirmed(text) {
+                continue;
+            }
+            if self.last_seen.insert(text.to_string(), now).is_none() && !self.last_reported.contains_key(text) {
+                self.last_reported.insert(text.to_string(), now);
+                new_qrcodes.push(qrcode.clone());
+            }
+        }
+        new_qrcodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qr::{CodeFormat, Detection};
+
+    fn qrcode(text: &str) -> QRCode {
+        let detection = Detection {
+            text: text.to_string(),
+            format: CodeFormat::QrCode,
+            corners: [[0., 0.], [0.5, 0.], [0.5, 0.5], [0., 0.5]],
+        };
+        QRCode::from_detection(&detection, (100, 100))
+    }
+
+    fn texts(qrcodes: Vec<QRCode>) -> Vec<String> {
+        qrcodes.iter().map(|qrcode| qrcode.text().to_string()).collect()
+    }
+
+    #[test]
+    fn confirmation_needs_k_of_n() {
+        let mut dedup = Dedup::default();
+        dedup.set_confirmation(2, 3);
+        assert!(dedup.update(&[qrcode("a"), qrcode("b")]).is_empty());
+        assert!(dedup.update(&[]).is_empty());
+        assert_eq!(texts(dedup.update(&[qrcode("b")])), ["b"]);
+        // "a" is in 1 of the last 3 frames, its first sighting has left the window
+        assert!(dedup.update(&[qrcode("a")]).is_empty());
+    }
+
+    #[test]
+    fn shrinking_confirmation_trims_recent() {
+        let mut dedup = Dedup::default();
+        dedup.set_confirmation(3, 5);
+        for _ in 0..5 {
+            dedup.update(&[qrcode("a")]);
+        }
+        assert_eq!(dedup.recent.len(), 5);
+        dedup.set_confirmation(1, 2);
+        assert_eq!(dedup.recent.len(), 2);
+        // a window smaller than the frames required is raised to it
+        dedup.set_confirmation(2, 1);
+        assert_eq!(dedup.confirm_window, 2);
+        assert_eq!(dedup.recent.len(), 2);
+    }
+
+    #[test]
+    fn cooldown_stops_rereporting() {
+        // with `gone_after` zero, a code has left the frame by the next update
+        let mut dedup = Dedup::new(Duration::ZERO);
+        dedup.set_gone_after(Duration::ZERO);
+        assert_eq!(texts(dedup.update(&[qrcode("a")])), ["a"]);
+        assert_eq!(texts(dedup.update(&[qrcode("a")])), ["a"]);
+
+        let mut dedup = Dedup::new(Duration::from_secs(60));
+        dedup.set_gone_after(Duration::ZERO);
+        assert_eq!(texts(dedup.update(&[qrcode("a")])), ["a"]);
+        assert!(dedup.update(&[]).is_empty());
+        assert!(dedup.update(&[qrcode("a")]).is_empty());
+        assert_eq!(texts(dedup.update(&[qrcode("b")])), ["b"]);
+    }
+}
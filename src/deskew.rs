@@ -0,0 +1,82 @@
+use image::{GrayImage, Luma};
+use imageproc::contrast::equalize_histogram;
+use imageproc::geometric_transformations::{warp, Interpolation, Projection};
+use zxingcpp::{BarcodeReader, PointI};
+
+use crate::qr::QRCode;
+
+/// Keystone corrections tried in turn, as (horizontal, vertical) fractions of the frame size by which
+/// one edge is pulled in, for codes tilted away from the camera.
+const KEYSTONES: [(f32, f32); 4] = [(0.1, 0.), (-0.1, 0.), (0., 0.1), (0., -0.1)];
+
+/// Retry a frame that had no codes with its histogram equalized, then with each keystone correction.
+///
+/// Positions are mapped back into the coordinates of the original frame.
+pub fn decode_normalized(reader: &BarcodeReader, grey_img: &GrayImage) -> Result<Vec<QRCode>, zxingcpp::Error> {
+    let frame_size = grey_img.dimensions();
+    let equalized = equalize_histogram(grey_img);
+    let qrcodes = decode_frame(reader, &equalized, frame_size)?;
+    if !qrcodes.is_empty() {
+        return Ok(qrcodes);
+    }
+
+    for keystone in KEYSTONES {
+        let Some(projection) = keystone_projection(frame_size, keystone) else {
+            continue;
+        };
+        let warped = warp(&equalized, &projection, Interpolation::Bilinear, Luma([0]));
+        let qrcodes = decode_frame(reader, &warped, frame_size)?;
+        if !qrcodes.is_empty() {
+            let inverse = projection.invert();
+            return Ok(qrcodes
+                .into_iter()
+                .map(|qrcode| {
+                    qrcode.map_position(|point| {
+                        let (x, y) = inverse * (point.x as f32, point.y as f32);
+                        PointI {
+                            x: x.round() as i32,
+                            y: y.round() as i32,
+                        }
+                    })
+                })
+                .collect());
+        }
+    }
+    Ok(Vec::new())
+}
+
+fn decode_frame(
+    reader: &BarcodeReader,
+    grey_img: &GrayImage,
+    frame_size: (u32, u32),
+) -> Result<Vec<QRCode>, zxingcpp::Error> {
+    Ok(reader
+        .from(grey_img)?
+        .iter()
+        .map(|barcode| QRCode::from_barcode(barcode, frame_size))
+        .collect())
+}
+
+/// A projection that pulls one edge of the frame inwards, a positive horizontal keystone narrows the
+/// top edge, negative the bottom, and likewise the left and right edges for vertical.
+fn keystone_projection((width, height): (u32, u32), (horizontal, vertical): (f32, f32)) -> Option<Projection> {
+    let (w, h) = (width as f32, height as f32);
+    let (dx, dy) = (horizontal.abs() * w, vertical.abs() * h);
+    let from = [(0., 0.), (w, 0.), (w, h), (0., h)];
+    let mut to = from;
+    if horizontal > 0. {
+        to[0].0 += dx;
+        to[1].0 -= dx;
+    } else if horizontal < 0. {
+        to[3].0 += dx;
+        to[2].0 -= dx;
+    }
+    if vertical > 0. {
+        to[0].1 += dy;
+        to[3].1 -= dy;
+    } else if vertical < 0. {
+        to[1].1 += dy;
+        to[2].1 -= dy;
+    }
+    Projection::from_control_points(from, to)
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+
+use regex::Regex;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+/// Named fields pulled out of a code's text by an `Extractor`, in the order their groups appear in the
+/// pattern.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fields(pub Vec<(String, String)>);
+
+impl Fields {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for Fields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (name, value)) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serialized as a JSON object, keeping the pattern's order.
+impl Serialize for Fields {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in &self.0 {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+/// Applies the `extract` setting's regex to decoded text, for codes carrying structured data like
+/// `ORDER:12345;ITEM:foo`, which `ORDER:(?<order>\d+);ITEM:(?<item>\w+)` splits into `order` and `item`.
+#[derive(Clone, Debug)]
+pub struct Extractor {
+    regex: Regex,
+}
+
+impl Extractor {
+    /// Compile `pattern`, which needs at least one named capture group to extract anything.
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|err| err.to_string())?;
+        if regex.capture_names().flatten().next().is_none() {
+            return Err(format!("{:?} has no named capture groups, e.g. (?<name>...)", pattern));
+        }
+        Ok(Self { regex })
+    }
+
+    /// The pattern's group names, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.regex.capture_names().flatten()
+    }
+
+    /// The named groups matched in `text`, `None` if the pattern doesn't match so the raw text is used
+    /// instead. Optional groups that didn't take part in the match are left out.
+    pub fn extract(&self, text: &str) -> Option<Fields> {
+        let captures = self.regex.captures(text)?;
+        Some(Fields(
+            self.names()
+                .filter_map(|name| Some((name.to_string(), captures.name(name)?.as_str().to_string())))
+                .collect(),
+        ))
+    }
+}
@@ -0,0 +1,30 @@
+use image::{GrayImage, Luma};
+use qrcode::types::QrError;
+use qrcode::QrCode;
+
+use crate::qr::{barcode_reader, decode_image, DecodeOptions, QRCode};
+
+/// Render `text` as a QR code, including its quiet zone, at least `min_size` pixels square.
+pub fn generate(text: &str, min_size: u32) -> Result<GrayImage, QrError> {
+    let code = QrCode::new(text.as_bytes())?;
+    Ok(code.render::<Luma<u8>>().min_dimensions(min_size, min_size).build())
+}
+
+/// Check a generated code decodes back to `text`, independent of the user's decode options.
+pub fn round_trips(text: &str, generated: &GrayImage) -> bool {
+    decode_generated(generated).iter().any(|code| code.text() == text)
+}
+
+/// Check a generated code decodes to exactly `bytes`, a stricter check than `round_trips` as text
+/// can survive a round trip in a different encoding.
+pub fn round_trips_bytes(bytes: &[u8], generated: &GrayImage) -> bool {
+    decode_generated(generated).iter().any(|code| code.bytes() == bytes)
+}
+
+fn decode_generated(generated: &GrayImage) -> Vec<QRCode> {
+    let options = DecodeOptions::default();
+    decode_image(&barcode_reader(&options), generated, &options).unwrap_or_else(|err| {
+        log::warn!("failed to decode generated code: {}", err);
+        Vec::new()
+    })
+}
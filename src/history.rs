@@ -0,0 +1,180 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::decode::lock;
+use crate::extract::{Extractor, Fields};
+use crate::qr::{normalize_corners, QRCode};
+
+/// One scan in the history, positions are the corners of the code in frame pixels.
+#[derive(Clone, Debug, Serialize)]
+pub struct HistoryEntry {
+    /// Seconds since the unix epoch.
+    pub timestamp: f64,
+    /// Presentation time of the camera frame the code was decoded from, see `QRCode::timestamp`.
+    pub frame_timestamp: Option<f64>,
+    pub text: String,
+    pub format: String,
+    pub position: [[i32; 2]; 4],
+    /// Width and height of the frame `position` is relative to.
+    pub frame_size: (u32, u32),
+    /// `position` as fractions of the frame size, only included in exports when requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized_position: Option<[[f64; 2]; 4]>,
+    /// Named groups of the `extract` pattern, if it matched `text`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Fields>,
+}
+
+impl HistoryEntry {
+    pub fn new(qrcode: &QRCode) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .unwrap_or_default();
+        Self {
+            timestamp,
+            frame_timestamp: qrcode.timestamp(),
+            text: qrcode.text().to_string(),
+            format: qrcode.format().to_string(),
+            position: qrcode.corners(),
+            frame_size: qrcode.frame_size(),
+            normalized_position: None,
+            fields: None,
+        }
+    }
+}
+
+/// Every unique scan this session, shared between the UI and the quit handler.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    entries: Arc<Mutex<Vec<HistoryEntry>>>,
+    extractor: Option<Extractor>,
+}
+
+impl History {
+    /// A history whose entries have fields pulled out of their text by `extractor`.
+    pub fn new(extractor: Option<Extractor>) -> Self {
+        Self {
+            entries: Arc::default(),
+            extractor,
+        }
+    }
+
+    pub fn push(&self, qrcode: &QRCode) {
+        let mut entry = HistoryEntry::new(qrcode);
+        entry.fields = self
+            .extractor
+            .as_ref()
+            .and_then(|extractor| extractor.extract(&entry.text));
+        lock(&self.entries).push(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.entries).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        lock(&self.entries).is_empty()
+    }
+
+    /// The text of the last `count` distinct codes scanned, newest first.
+    pub fn recent(&self, count: usize) -> Vec<String> {
+        let mut recent: Vec<String> = Vec::new();
+        for entry in lock(&self.entries).iter().rev() {
+            if recent.len() == count {
+                break;
+            }
+            if !recent.contains(&entry.text) {
+                recent.push(entry.text.clone());
+            }
+        }
+        recent
+    }
+
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        lock(&self.entries).clone()
+    }
+
+    /// Entries whose text contains `filter`, ignoring case, every entry for an empty filter.
+    pub fn filtered(&self, filter: &str) -> Vec<HistoryEntry> {
+        let filter = filter.to_lowercase();
+        lock(&self.entries)
+            .iter()
+            .filter(|entry| entry.text.to_lowercase().contains(&filter))
+            .cloned()
+            .collect()
+    }
+
+    /// Write the history to `path`, as JSON if it has a `.json` extension, otherwise CSV.
+    ///
+    /// With `normalized`, JSON entries also include positions as fractions of the frame size.
+    pub fn export(&self, path: &Path, normalized: bool) -> Result<()> {
+        let mut entries = self.entries();
+        if normalized {
+            for entry in &mut entries {
+                entry.normalized_position = Some(normalize_corners(entry.position, entry.frame_size));
+            }
+        }
+        let content = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::to_string_pretty(&entries)?,
+            _ => to_csv(&entries),
+        };
+        fs::write(path, content).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+const CSV_HEADER: &str = "timestamp,frame_timestamp,text,format,top_left_x,top_left_y,top_right_x,top_right_y,\
+bottom_right_x,bottom_right_y,bottom_left_x,bottom_left_y";
+
+/// Extracted fields get a column each after the fixed ones, empty for entries without them.
+fn to_csv(entries: &[HistoryEntry]) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    for (name, _) in entries
+        .iter()
+        .filter_map(|entry| entry.fields.as_ref())
+        .flat_map(|fields| &fields.0)
+    {
+        if !names.contains(&name.as_str()) {
+            names.push(name);
+        }
+    }
+    let mut csv = CSV_HEADER.to_string();
+    for name in &names {
+        csv.push(',');
+        csv.push_str(&csv_field(name));
+    }
+    csv.push('\n');
+    for entry in entries {
+        let mut fields = vec![
+            format!("{:.3}", entry.timestamp),
+            entry
+                .frame_timestamp
+                .map(|timestamp| format!("{:.6}", timestamp))
+                .unwrap_or_default(),
+            csv_field(&entry.text),
+            csv_field(&entry.format),
+        ];
+        fields.extend(entry.position.iter().flatten().map(ToString::to_string));
+        fields.extend(names.iter().map(|name| {
+            let value = entry.fields.as_ref().and_then(|fields| fields.get(name));
+            csv_field(value.unwrap_or_default())
+        }));
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quote a field if it contains anything CSV treats specially.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
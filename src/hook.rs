@@ -0,0 +1,34 @@
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::qr::QRCode;
+use crate::sanitize::{escape_control, shell_quote};
+
+/// Replace every `{}` in `command` with the code's text, escaped and quoted for the shell.
+pub fn substitute(command: &str, qrcode: &QRCode) -> String {
+    command.replace("{}", &shell_quote(&escape_control(qrcode.text())))
+}
+
+/// Start the `--on-scan` command for a code without waiting for it, so a slow command can't hold
+/// up decoding.
+///
+/// Decoded text comes from whatever is put in front of the camera so must be treated as hostile.
+/// It's stripped of control characters and single quoted before substitution, so it always reaches
+/// the command as one literal argument, but the command is still run by `sh`: anything it does with
+/// that argument (e.g. `eval` or handing it to another shell) is only as safe as the command makes it.
+pub fn run_on_scan(command: &str, qrcode: &QRCode) {
+    let command = substitute(command, qrcode);
+    match Command::new("sh").arg("-c").arg(&command).stdin(Stdio::null()).spawn() {
+        Ok(mut child) => {
+            // reap the child once it exits, rather than leaving a zombie process behind
+            thread::spawn(move || {
+                if let Ok(status) = child.wait() {
+                    if !status.success() {
+                        log::warn!("on-scan command exited with {}", status);
+                    }
+                }
+            });
+        }
+        Err(err) => log::error!("failed to run on-scan command: {}", err),
+    }
+}
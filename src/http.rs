@@ -0,0 +1,168 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::DynamicImage;
+use serde_json::json;
+
+use crate::decode::Decoder;
+use crate::history::HistoryEntry;
+
+/// How long `/frame.jpg` waits for a frame when the preview has just taken the newest one.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a connection can take to send its request, or to accept each write of the response, before
+/// it's dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most bytes read for a request line and its headers, anything longer is refused.
+const MAX_REQUEST_BYTES: u64 = 8192;
+
+/// Most connections handled at once, more are turned away rather than each getting a thread.
+const MAX_CONNECTIONS: usize = 16;
+
+const JPEG_QUALITY: u8 = 80;
+
+/// Serve the decoder's latest frame as `/frame.jpg` and the codes in it as `/results.json` on `addr`.
+/// Anyone who can reach the address can see the camera, so `--http-bind` defaults to 127.0.0.1.
+///
+/// Each connection is handled on its own thread and frames are encoded there, so requests never hold
+/// up capture or decoding. Returns once the port is bound, an error if it can't be.
+pub fn serve(addr: (IpAddr, u16), decoder: Decoder) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    log::info!("serving frames and results on http://{}", listener.local_addr()?);
+    let connections = Arc::new(AtomicUsize::new(0));
+    thread::Builder::new().name("http".to_string()).spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => match ConnectionSlot::acquire(&connections) {
+                    Some(slot) => {
+                        let decoder = decoder.clone();
+                        thread::spawn(move || {
+                            if let Err(err) = handle(stream, &decoder) {
+                                log::debug!("http connection failed: {}", err);
+                            }
+                            drop(slot);
+                        });
+                    }
+                    None => {
+                        log::debug!("turning away http connection, {} already open", MAX_CONNECTIONS);
+                        // written without blocking so a client that never reads can't hold up the accept
+                        // loop, the 503 fits in a new connection's send buffer so is still sent in practice
+                        let mut stream = &stream;
+                        if stream.set_nonblocking(true).is_ok() {
+                            respond(
+                                &mut stream,
+                                "503 Service Unavailable",
+                                "text/plain",
+                                b"too many connections",
+                            )
+                            .ok();
+                        }
+                    }
+                },
+                Err(err) => log::warn!("failed to accept http connection: {}", err),
+            }
+        }
+    })?;
+    Ok(())
+}
+
+/// Counts a connection towards `MAX_CONNECTIONS` until it's dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn acquire(connections: &Arc<AtomicUsize>) -> Option<Self> {
+        connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < MAX_CONNECTIONS).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Self(connections.clone()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+fn handle(stream: TcpStream, decoder: &Decoder) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    // a client that stops reading would otherwise keep its connection slot forever
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST_BYTES));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // the headers aren't needed, but are read so the client isn't reset before it's sent them all
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut stream = &stream;
+    if reader.get_ref().limit() == 0 {
+        return respond(
+            &mut stream,
+            "431 Request Header Fields Too Large",
+            "text/plain",
+            b"request too large",
+        );
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    // ignore any query string, e.g. one added by a dashboard to defeat caching
+    let path = path.split('?').next().unwrap_or_default();
+    match (method, path) {
+        ("GET", "/frame.jpg") => match decoder.next_img(FRAME_TIMEOUT) {
+            Some(img) => {
+                let mut jpeg = Vec::new();
+                let rgb = DynamicImage::ImageRgba8(img).to_rgb8();
+                match JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY).encode_image(&rgb) {
+                    Ok(()) => respond(&mut stream, "200 OK", "image/jpeg", &jpeg),
+                    Err(err) => respond(
+                        &mut stream,
+                        "500 Internal Server Error",
+                        "text/plain",
+                        err.to_string().as_bytes(),
+                    ),
+                }
+            }
+            None => respond(&mut stream, "503 Service Unavailable", "text/plain", b"no frame yet"),
+        },
+        ("GET", "/results.json") => {
+            // positioned like the preview, so they line up with /frame.jpg
+            let codes: Vec<HistoryEntry> = decoder
+                .latest_qrcodes()
+                .into_iter()
+                .map(|qrcode| HistoryEntry::new(&decoder.to_display(qrcode)))
+                .collect();
+            let body = json!({ "codes": codes }).to_string();
+            respond(&mut stream, "200 OK", "application/json", body.as_bytes())
+        }
+        ("GET", _) => respond(&mut stream, "404 Not Found", "text/plain", b"not found"),
+        _ => respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"method not allowed",
+        ),
+    }
+}
+
+fn respond(stream: &mut impl Write, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
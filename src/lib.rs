@@ -0,0 +1,27 @@
+pub mod camera;
+pub mod cli;
+pub mod decode;
+pub mod dedup;
+pub mod deskew;
+pub mod extract;
+pub mod generate;
+pub mod history;
+pub mod hook;
+pub mod http;
+pub mod logs;
+pub mod notify;
+pub mod output;
+pub mod overlay;
+pub mod payload;
+pub mod product;
+pub mod qr;
+pub mod sanitize;
+pub mod scan_action;
+pub mod sequence;
+pub mod settings;
+pub mod snapshot;
+pub mod stats;
+pub mod tally;
+pub mod tile;
+pub mod video;
+pub mod window;
@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use log::{Level, Log, Metadata, Record};
+
+use crate::decode::lock;
+
+/// Most log lines kept for the log panel, older lines are dropped.
+pub const MAX_LOG_LINES: usize = 500;
+
+/// Lines at this level and above from this app are kept, whatever `RUST_LOG` says.
+const CAPTURE_LEVEL: Level = Level::Info;
+
+/// Recent log lines from this app, for showing in the window where there's no terminal to see them,
+/// e.g. when run as a packaged .app.
+#[derive(Clone, Debug, Default)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl LogBuffer {
+    /// Up to `count` of the most recent lines, oldest first.
+    pub fn tail(&self, count: usize) -> Vec<String> {
+        let lines = lock(&self.lines);
+        lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+    }
+
+    fn push(&self, line: String) {
+        let mut lines = lock(&self.lines);
+        if lines.len() == MAX_LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+/// Passes records to env_logger as before, and keeps this app's own lines in a `LogBuffer`.
+struct Logger {
+    env_logger: env_logger::Logger,
+    buffer: LogBuffer,
+    started: Instant,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.env_logger.enabled(metadata) || captured(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.env_logger.matches(record) {
+            self.env_logger.log(record);
+        }
+        if captured(record.metadata()) {
+            self.buffer.push(format!(
+                "{:>8.3}s {:<5} {}",
+                self.started.elapsed().as_secs_f64(),
+                record.level(),
+                record.args()
+            ));
+        }
+    }
+
+    fn flush(&self) {
+        self.env_logger.flush();
+    }
+}
+
+fn captured(metadata: &Metadata) -> bool {
+    metadata.level() <= CAPTURE_LEVEL && metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+}
+
+/// Install the logger in place of `env_logger::init`, returning the buffer of captured lines.
+pub fn init() -> LogBuffer {
+    let env_logger = env_logger::Builder::from_default_env().build();
+    let max_level = env_logger.filter().max(CAPTURE_LEVEL.to_level_filter());
+    let buffer = LogBuffer::default();
+    let logger = Logger {
+        env_logger,
+        buffer: buffer.clone(),
+        started: Instant::now(),
+    };
+    match log::set_logger(Box::leak(Box::new(logger))) {
+        Ok(()) => log::set_max_level(max_level),
+        Err(err) => eprintln!("failed to set logger: {}", err),
+    }
+    buffer
+}
@@ -1,88 +1,1800 @@
+use clap::Parser;
 use gpui::{
-    actions, div, img, prelude::*, px, size, App, Application, Bounds, Context, ImageSource, KeyBinding, Menu,
-    MenuItem, Point, RenderImage, SharedString, Task, Timer, TitlebarOptions, Window, WindowBounds, WindowOptions,
+    actions, canvas, div, fill, img, outline, point, prelude::*, px, relative, size, Animation, AnimationExt, App,
+    Application, Bounds, ClipboardEntry, ClipboardItem, ContentMask, Context, Corners, Entity, ExternalPaths,
+    FocusHandle, FontWeight, ImageSource, KeyBinding, KeyDownEvent, Keystroke, Menu, MenuItem, ModifiersChangedEvent,
+    MouseButton, PathBuilder, Pixels, Point, RenderImage, SharedString, Task, Timer, TitlebarOptions, Window,
+    WindowBounds, WindowOptions,
 };
-use image::{Frame, RgbaImage};
-use std::{sync::Arc, time::Duration};
+use image::imageops::grayscale;
+use image::{DynamicImage, Frame, GrayImage, RgbaImage};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
+    path::PathBuf,
+    process::ExitCode,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use qrcam::camera::{DeviceCapture, DeviceInfo, Exposure, FocusMode};
+use qrcam::cli::{self, Args};
+use qrcam::decode::{Decoder, Mirror};
+use qrcam::extract::Extractor;
+use qrcam::generate::{generate, round_trips, round_trips_bytes};
+use qrcam::history::History;
+use qrcam::hook::run_on_scan;
+use qrcam::http;
+use qrcam::logs::{self, LogBuffer};
+use qrcam::notify::{announce, beep, notify};
+use qrcam::output::{Output, OutputFormat};
+use qrcam::overlay::{average_luma, FrameRegion, FrameTransform};
+use qrcam::product::{self, is_product_format};
+use qrcam::qr::{barcode_reader, decode_image, Backend, CodeFormat, DecodeOptions, QRCode};
+use qrcam::sanitize::{escape_control, truncate_for_display, MAX_DISPLAY_CHARS};
+use qrcam::scan_action::{self, ScanAction};
+use qrcam::settings::Settings;
+use qrcam::snapshot;
+use qrcam::tally::Tally;
+use qrcam::video::{VideoPlayback, VideoSource};
+use qrcam::window::set_always_on_top;
+
+/// How long a status message stays on screen.
+const STATUS_DURATION: Duration = Duration::from_secs(4);
+
+/// How long the presentation mode overlay takes to fade after its code leaves the frame.
+const PRESENTATION_FADE: Duration = Duration::from_secs(3);
+
+/// How long an image file dropped on the window is shown in place of the preview.
+const DROPPED_DURATION: Duration = Duration::from_secs(4);
+
+/// How long the torch stays on, then off, with `capture.torch_pulse`. Slow enough not to be a strobe,
+/// while still leaving most of each second's frames free of glare.
+const TORCH_PULSE: Duration = Duration::from_millis(500);
+
+/// How many recent codes cmd-1 to cmd-9, then cmd-0, copy.
+const RECENTS: usize = 10;
+
+/// How long to wait for a still from `CaptureStill` before giving up on it.
+const STILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the flash over the preview takes to fade after a new code is scanned.
+const FLASH_DURATION: Duration = Duration::from_millis(200);
+
+/// Fraction of the way the lock on zoom moves towards its target each frame, lower is smoother but slower.
+const LOCK_ON_SMOOTHING: f32 = 0.2;
+
+/// Radius of the dots marking each corner of a code when corner markers are shown.
+const CORNER_RADIUS: f32 = 4.;
+
+/// Most history entries listed in the history panel, the newest are shown.
+const HISTORY_PANEL_ROWS: usize = 20;
+
+/// How often the camera's exposure is read for the log panel.
+const EXPOSURE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the window checks the decoder for new frames and codes, unless set in the settings.
+const DEFAULT_UI_INTERVAL_MS: u64 = 37;
+
+/// Bounds and step of the decode and UI intervals adjusted in the log panel, in milliseconds.
+const INTERVAL_MIN_MS: u64 = 5;
+const INTERVAL_MAX_MS: u64 = 500;
+const INTERVAL_STEP_MS: u64 = 4;
 
-use camera::{DeviceCapture, DeviceInfo};
-use decode::Decoder;
-use qr::QRCode;
+/// How many of the most recent log lines the log panel shows.
+const LOG_PANEL_LINES: usize = 12;
 
-mod camera;
-mod decode;
-mod qr;
+/// Frames of the spinner shown while the camera is starting.
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+/// Where the camera is in getting from window open to showing frames.
+enum CameraState {
+    /// Looking for capture devices.
+    Discovering,
+    /// The session is starting on the named camera, waiting for its first frame.
+    Starting(SharedString),
+    /// Frames are arriving from the named camera.
+    Running(SharedString),
+    /// The camera was stopped after a period of inactivity, see `Settings::idle_timeout_secs`.
+    Idle,
+    Error(SharedString),
+}
+
+/// Minimum size of the codes regenerated for round trip verification, large enough to decode reliably
+/// even though they're shown as thumbnails.
+const VERIFY_SIZE: u32 = 256;
+
+/// A QR code generated from a scanned code's text, for the split view and round trip verification.
+struct Regenerated {
+    text: String,
+    image: Option<Arc<RenderImage>>,
+    // whether the generated code decodes back to the same text, or bytes when verifying
+    round_trips: bool,
+}
+
+impl Regenerated {
+    /// Generate a code from `text`, checking it with `check` (given the text and generated image).
+    fn new(text: String, min_size: u32, check: impl FnOnce(&str, &GrayImage) -> bool) -> Self {
+        match generate(&text, min_size) {
+            Ok(grey) => Self {
+                round_trips: check(&text, &grey),
+                image: Some(render_image(DynamicImage::ImageLuma8(grey).into_rgba8())),
+                text,
+            },
+            Err(err) => {
+                log::warn!("failed to generate code: {}", err);
+                Self {
+                    text,
+                    image: None,
+                    round_trips: false,
+                }
+            }
+        }
+    }
+}
+
+/// Frames per second reaching the preview, measured over roughly a second at a time.
+struct FrameRate {
+    since: Instant,
+    frames: u32,
+    fps: Option<f32>,
+}
+
+impl FrameRate {
+    fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            frames: 0,
+            fps: None,
+        }
+    }
+
+    fn count_frame(&mut self) {
+        self.frames += 1;
+        let elapsed = self.since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = Some(self.frames as f32 / elapsed.as_secs_f32());
+            self.since = Instant::now();
+            self.frames = 0;
+        }
+    }
+
+    fn label(&self) -> String {
+        match self.fps {
+            Some(fps) => format!("{:.1} fps", fps),
+            None => "- fps".to_string(),
+        }
+    }
+}
+
+/// An image file dropped on the window, or a still captured from the camera, shown in place of the preview
+/// with the codes found in it.
+struct DroppedImage {
+    name: String,
+    // kept to decode again with `DecodeCurrent`, without reading the file again
+    rgba: RgbaImage,
+    image: Arc<RenderImage>,
+    qrcodes: Vec<QRCode>,
+    // texts already recorded as scans, so decoding again only redraws the boxes and status
+    reported: HashSet<String>,
+    shown: Instant,
+}
+
+/// A frame frozen for decoding again with different options, to diagnose codes that are missed.
+struct DebugPanel {
+    frame: GrayImage,
+    options: DecodeOptions,
+    results: Result<Vec<QRCode>, String>,
+}
+
+impl DebugPanel {
+    fn new(frame: GrayImage, options: DecodeOptions) -> Self {
+        let mut panel = Self {
+            frame,
+            options,
+            results: Ok(Vec::new()),
+        };
+        panel.redecode();
+        panel
+    }
+
+    fn update_options(&mut self, f: impl FnOnce(&mut DecodeOptions)) {
+        f(&mut self.options);
+        self.redecode();
+    }
+
+    fn redecode(&mut self) {
+        self.results =
+            decode_image(&barcode_reader(&self.options), &self.frame, &self.options).map_err(|err| err.to_string());
+    }
+}
 
 struct ImageDisplay {
-    decoder: Option<Decoder>,
+    decoder: Decoder,
+    settings: Settings,
+    // what's written to the settings file: what was loaded plus changes made in the window, without
+    // command line or environment overrides. Shared by every pane so one saving doesn't undo another's
+    // changes
+    saved_settings: Rc<RefCell<Settings>>,
+    history: History,
+    // the history panel's filter text, `None` while the panel is hidden
+    history_filter: Option<String>,
+    logs: LogBuffer,
+    // show recent log lines, frame rate and dropped frames, for diagnosing camera problems
+    show_logs: bool,
+    frame_rate: FrameRate,
+    // the camera's exposure and when it was read, only while the log panel is shown
+    exposure: Option<(Exposure, Instant)>,
+    // counts of each code coming into view, only in tally mode
+    tally: Option<Tally>,
     task: Option<Task<()>>,
-    camera: Option<SharedString>,
+    state: CameraState,
+    // the camera in use, kept so it's the one restarted after going idle
+    device: Option<DeviceInfo>,
+    capture: Option<DeviceCapture>,
+    // a video file decoded in place of the camera, and its playback while running
+    video: Option<VideoSource>,
+    playback: Option<VideoPlayback>,
+    // where to record the camera to, recording is only available when this is set
+    record_path: Option<PathBuf>,
+    // whether the window title names this display's camera, not in a grid where it names them all
+    titles_window: bool,
+    // last time a code was in frame or the user pressed a key or clicked
+    last_activity: Instant,
+    // a brief message shown in the status area, e.g. the result of decoding the clipboard
+    status: Option<(SharedString, Instant)>,
+    focus: FocusMode,
     qrcodes: Vec<QRCode>,
+    // selection is tracked by text so it survives the decode order changing between frames
+    selected: Option<String>,
+    // codes the user has dismissed, hidden until they leave the frame and come back
+    dismissed: HashSet<String>,
+    // most recently scanned code and when it was last in frame, for presentation mode
+    latest: Option<(String, Instant)>,
+    // how many flashes there have been, to restart the animation for each, and when the last started
+    flash: Option<(usize, Instant)>,
+    regenerated: Option<Regenerated>,
+    // codes in frame regenerated from their text, keyed by text, while verifying round trips
+    verified: HashMap<String, Regenerated>,
+    // shows the `extract` setting's fields in place of the text of codes it matches
+    extractor: Option<Extractor>,
+    debug: Option<DebugPanel>,
+    // whether cmd is held, showing which recent codes cmd-1 to cmd-0 copy
+    show_recents: bool,
+    // whether the torch is lit and when it was last switched, while pulsing it
+    torch: Option<(bool, Instant)>,
+    // zoom in on the code when there's only one in frame
+    lock_on: bool,
+    // mark each code's corners and outline its exact quadrilateral, to show how it was located
+    show_corners: bool,
+    // show a crosshair and how far the camera should move for codes to be the calibrated size
+    calibrating: bool,
+    // where the preview was last painted, to position labels over its boxes
+    preview_bounds: Rc<Cell<Option<Bounds<Pixels>>>>,
+    // the region of the frame currently shown while locked on, `None` when showing the whole frame
+    zoom: Option<FrameRegion>,
+    // product names looked up for EAN/UPC codes, `None` while the lookup is in flight or if unknown
+    products: HashMap<String, Option<SharedString>>,
+    // average brightness of the frame under each code's box, for auto-contrast box colours
+    box_luma: HashMap<String, f32>,
+    // whether the window was last set floating, so its level is only changed when needed
+    on_top_applied: Option<bool>,
+    // an image file dropped on the window, shown in place of the preview until it expires or a key is
+    // pressed, and files dropped with it still to show
+    dropped: Option<DroppedImage>,
+    dropped_queue: VecDeque<PathBuf>,
     img: Option<RgbaImage>,
+    frame_size: Option<(u32, u32)>,
     last_image: Option<Arc<RenderImage>>,
+    retired_images: Vec<Arc<RenderImage>>,
+    focus_handle: FocusHandle,
 }
 
 impl ImageDisplay {
-    fn new(decoder: Decoder) -> Self {
+    fn new(
+        decoder: Decoder,
+        settings: Settings,
+        saved_settings: Rc<RefCell<Settings>>,
+        history: History,
+        logs: LogBuffer,
+        tally: Option<Tally>,
+        record_path: Option<PathBuf>,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        let extractor = extractor(&settings);
         Self {
-            decoder: Some(decoder),
+            decoder,
+            settings,
+            saved_settings,
+            history,
+            history_filter: None,
+            logs,
+            show_logs: false,
+            frame_rate: FrameRate::new(),
+            exposure: None,
+            tally,
             task: None,
-            camera: None,
+            state: CameraState::Discovering,
+            device: None,
+            capture: None,
+            video: None,
+            playback: None,
+            record_path,
+            titles_window: false,
+            last_activity: Instant::now(),
+            status: None,
+            focus: FocusMode::default(),
             qrcodes: Vec::new(),
+            selected: None,
+            dismissed: HashSet::new(),
+            latest: None,
+            flash: None,
+            regenerated: None,
+            verified: HashMap::new(),
+            extractor,
+            debug: None,
+            show_recents: false,
+            torch: None,
+            lock_on: false,
+            show_corners: false,
+            calibrating: false,
+            preview_bounds: Rc::new(Cell::new(None)),
+            zoom: None,
+            products: HashMap::new(),
+            box_luma: HashMap::new(),
+            on_top_applied: None,
+            dropped: None,
+            dropped_queue: VecDeque::new(),
             img: None,
+            frame_size: None,
             last_image: None,
+            retired_images: Vec::new(),
+            focus_handle: cx.focus_handle(),
         }
     }
 
     fn start(&mut self, window: &mut Window, cx: &mut Context<Self>) {
-        let Some(decoder) = self.decoder.take() else {
+        if self.task.is_some() {
             return;
-        };
+        }
+        let decoder = self.decoder.clone();
+        let capture_options = self.settings.capture.clone();
+        let device = self.device.clone();
+        let video = self.video.clone();
+        // the first frame from a newly started camera may be a different size to anything shown before
+        self.reset_frame(window);
+        self.state = CameraState::Discovering;
+        self.last_activity = Instant::now();
 
+        // every update fails once the window has closed, the task then ends, dropping any capture or
+        // playback it still holds so the camera is released
         self.task = Some(cx.spawn_in(window, async move |view, cx| {
-            let devices = DeviceInfo::find_all();
-            let device_info = devices.first().unwrap();
+            if let Some(video) = video {
+                let name = video.name();
+                let video_decoder = decoder.clone();
+                let playback = cx
+                    .background_spawn(async move { VideoPlayback::start(&video, video_decoder) })
+                    .await;
+                match playback {
+                    Ok(playback) => {
+                        let Ok(()) = view.update(cx, |view, cx| {
+                            view.playback = Some(playback);
+                            view.state = CameraState::Starting(name.into());
+                            cx.notify();
+                        }) else {
+                            return;
+                        };
+                    }
+                    Err(err) => {
+                        log::error!("failed to play video: {}", err);
+                        view.update(cx, |view, cx| {
+                            view.state = CameraState::Error(format!("Failed to play video: {}", err).into());
+                            cx.notify();
+                        })
+                        .ok();
+                        return;
+                    }
+                }
+            } else {
+                let device_info = match device {
+                    Some(device_info) => device_info,
+                    None => {
+                        let facing = capture_options.facing;
+                        let device_info = cx
+                            .background_spawn(async move { DeviceInfo::find_preferred(facing) })
+                            .await;
+                        let Some(device_info) = device_info else {
+                            log::error!("no camera found");
+                            view.update(cx, |view, cx| {
+                                view.state = CameraState::Error("No camera found".into());
+                                cx.notify();
+                            })
+                            .ok();
+                            return;
+                        };
+                        device_info
+                    }
+                };
 
-            view.update(cx, |view, cx| {
-                view.camera = Some(device_info.name.clone().into());
-                cx.notify();
-            })
-            .unwrap();
+                let Ok(()) = view.update_in(cx, |view, window, cx| {
+                    view.device = Some(device_info.clone());
+                    view.state = CameraState::Starting(device_info.to_string().into());
+                    if view.titles_window {
+                        window.set_window_title(&window_title(&[&device_info.name]));
+                    }
+                    cx.notify();
+                }) else {
+                    return;
+                };
 
-            let _capture = DeviceCapture::start(&device_info, decoder.clone()).unwrap();
+                let capture =
+                    DeviceCapture::start_with_retries(&device_info, &decoder, &capture_options, Timer::after).await;
+                let capture = match capture {
+                    Ok(capture) => capture,
+                    Err(err) => {
+                        log::error!("failed to start camera: {}", err);
+                        view.update(cx, |view, cx| {
+                            view.state = CameraState::Error(format!("Failed to start camera: {}", err).into());
+                            cx.notify();
+                        })
+                        .ok();
+                        return;
+                    }
+                };
+                let Ok(()) = view.update(cx, |view, cx| {
+                    view.capture = Some(capture);
+                    view.start_recording(cx);
+                }) else {
+                    return;
+                };
+            }
 
             loop {
-                Timer::after(Duration::from_millis(37)).await;
+                let Ok(interval) = view.update(cx, |view, _| view.ui_interval()) else {
+                    return;
+                };
+                Timer::after(interval).await;
                 let opt_img = decoder.take_img();
                 let opt_qrcodes = decoder.take_qrcodes();
+                let new_qrcodes = decoder.take_new_qrcodes();
 
-                if opt_img.is_some() || opt_qrcodes.is_some() {
-                    view.update(cx, |view, cx| {
+                if opt_img.is_some() || opt_qrcodes.is_some() || !new_qrcodes.is_empty() {
+                    let Ok(()) = view.update(cx, |view, cx| {
                         if let Some(img) = opt_img {
                             view.img = Some(img);
+                            view.frame_rate.count_frame();
+                            view.poll_exposure();
+                            if let CameraState::Starting(name) = &view.state {
+                                view.state = CameraState::Running(name.clone());
+                            }
                         }
                         if let Some(qrcodes) = opt_qrcodes {
-                            view.qrcodes = qrcodes;
+                            if !qrcodes.is_empty() {
+                                view.last_activity = Instant::now();
+                            }
+                            view.set_qrcodes(qrcodes.into_iter().map(|code| decoder.to_display(code)).collect());
                         }
+                        view.on_new_qrcodes(&new_qrcodes, cx);
                         cx.notify();
+                    }) else {
+                        return;
+                    };
+                }
+                if view
+                    .update(cx, |view, cx| {
+                        view.pulse_torch();
+                        view.stop_if_idle(cx)
                     })
-                    .unwrap();
+                    .unwrap_or(true)
+                {
+                    return;
                 }
             }
         }));
     }
+
+    /// With `capture.torch_pulse`, switch the torch on or off every `TORCH_PULSE`, so the decoder can
+    /// skip the frames with its glare.
+    fn pulse_torch(&mut self) {
+        if !self.settings.capture.torch_pulse {
+            return;
+        }
+        let Some(capture) = self.capture.as_ref().filter(|capture| capture.has_torch()) else {
+            return;
+        };
+        if self.torch.is_some_and(|(_, switched)| switched.elapsed() < TORCH_PULSE) {
+            return;
+        }
+        let lit = !self.torch.is_some_and(|(lit, _)| lit);
+        // the decoder is told before the torch is lit and after it's put out, so it never decodes a
+        // frame the torch might be in
+        if lit {
+            self.decoder.set_torch_lit(true);
+        }
+        if let Err(err) = capture.set_torch(lit) {
+            log::debug!("failed to switch torch: {}", err);
+        }
+        if !lit {
+            self.decoder.set_torch_lit(false);
+        }
+        self.torch = Some((lit, Instant::now()));
+    }
+
+    /// Stop the camera if nothing has happened for the configured idle timeout, returning whether it
+    /// was stopped.
+    fn stop_if_idle(&mut self, cx: &mut Context<Self>) -> bool {
+        let Some(timeout) = self.settings.idle_timeout_secs.map(Duration::from_secs) else {
+            return false;
+        };
+        if self.last_activity.elapsed() < timeout {
+            return false;
+        }
+        log::info!("stopping camera after {}s idle", timeout.as_secs());
+        // dropping the capture stops its session and releases the camera
+        self.capture = None;
+        self.playback = None;
+        // the torch goes out with the session, decoding shouldn't wait for it when the camera restarts
+        if self.torch.take().is_some() {
+            self.decoder.set_torch_lit(false);
+        }
+        self.state = CameraState::Idle;
+        cx.notify();
+        true
+    }
+
+    /// Note user activity, restarting the camera if it was stopped for being idle.
+    fn wake(&mut self, cx: &mut Context<Self>) {
+        self.last_activity = Instant::now();
+        if matches!(self.state, CameraState::Idle) {
+            // the next render starts a new task, on the same device
+            self.task = None;
+            cx.notify();
+        }
+    }
+
+    fn start_recording(&mut self, cx: &mut Context<Self>) {
+        let (Some(capture), Some(path)) = (self.capture.as_mut(), self.record_path.as_ref()) else {
+            return;
+        };
+        match capture.start_recording(path) {
+            Ok(()) => log::info!("recording to {}", path.display()),
+            Err(err) => {
+                log::error!("failed to start recording: {}", err);
+                self.show_status(format!("Failed to start recording: {}", err), cx);
+            }
+        }
+        cx.notify();
+    }
+
+    /// Forget the current frame and anything positioned relative to it.
+    fn reset_frame(&mut self, window: &mut Window) {
+        self.frame_size = None;
+        self.zoom = None;
+        self.qrcodes.clear();
+        if let Some(last_image) = self.last_image.take() {
+            self.retired_images.push(last_image);
+        }
+        self.drop_retired_images(window);
+    }
+
+    /// Free the textures of frames no longer displayed, keeping any that are still referenced
+    /// elsewhere (e.g. by an element from a previous render) until a later frame.
+    fn drop_retired_images(&mut self, window: &mut Window) {
+        self.retired_images.retain(|image| {
+            if Arc::strong_count(image) > 1 {
+                return true;
+            }
+            if let Err(err) = window.drop_image(image.clone()) {
+                log::warn!("failed to drop frame image: {:?}", err);
+            }
+            false
+        });
+    }
+
+    fn on_new_qrcodes(&mut self, new_qrcodes: &[QRCode], cx: &mut Context<Self>) {
+        new_qrcodes.iter().for_each(|code| self.history.push(code));
+        if let Some(tally) = &self.tally {
+            new_qrcodes.iter().for_each(|code| tally.record(code));
+        }
+        if let Some(code) = new_qrcodes.last() {
+            self.latest = Some((code.text().to_string(), Instant::now()));
+            let count = self.flash.map_or(0, |(count, _)| count + 1);
+            self.flash = Some((count, Instant::now()));
+        }
+        new_qrcodes.iter().for_each(announce);
+        if self.settings.notify {
+            new_qrcodes.iter().for_each(notify);
+        }
+        for code in new_qrcodes {
+            self.run_scan_actions(code, cx);
+        }
+        if self.settings.product_lookup.enabled {
+            new_qrcodes
+                .iter()
+                .filter(|code| is_product_format(code.format()))
+                .for_each(|code| self.lookup_product(code.text(), cx));
+        }
+    }
+
+    fn run_scan_actions(&self, code: &QRCode, cx: &mut Context<Self>) {
+        for action in &self.settings.on_scan {
+            match action {
+                ScanAction::Copy => cx.write_to_clipboard(ClipboardItem::new_string(code.text().to_string())),
+                ScanAction::OpenUrl if scan_action::is_web_url(code.text()) => cx.open_url(code.text().trim()),
+                ScanAction::OpenUrl => log::info!("not opening {:?}, it isn't a web URL", code.text()),
+                ScanAction::Notify => notify(code),
+                ScanAction::Beep => beep(),
+                ScanAction::RunCommand { command } => run_on_scan(command, code),
+                ScanAction::AppendToFile { path } => {
+                    if let Err(err) = scan_action::append_to_file(path, code) {
+                        log::error!("failed to append scan: {:?}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn lookup_product(&mut self, code: &str, cx: &mut Context<Self>) {
+        if self.products.contains_key(code) {
+            return;
+        }
+        self.products.insert(code.to_string(), None);
+        let config = self.settings.product_lookup.clone();
+        let code = code.to_string();
+        cx.spawn(async move |view, cx| {
+            let lookup_code = code.clone();
+            let result = cx
+                .background_spawn(async move { product::lookup(&config, &lookup_code) })
+                .await;
+            view.update(cx, |view, cx| {
+                match result {
+                    Ok(name) => {
+                        view.products.insert(code, name.map(Into::into));
+                    }
+                    Err(err) => {
+                        // forget the code so the lookup is retried next time it's scanned
+                        log::warn!("product lookup failed: {:?}", err);
+                        view.products.remove(&code);
+                    }
+                }
+                cx.notify();
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    fn set_qrcodes(&mut self, mut qrcodes: Vec<QRCode>) {
+        // results decoded from a frame of a different size can't be overlaid on the current one
+        if let Some(frame_size) = self.frame_size {
+            qrcodes.retain(|code| code.frame_size() == frame_size);
+        }
+        self.dismissed
+            .retain(|text| qrcodes.iter().any(|code| code.text() == text));
+        qrcodes.retain(|code| !self.dismissed.contains(code.text()));
+        if let Some((text, last_seen)) = self.latest.as_mut() {
+            if qrcodes.iter().any(|code| code.text() == text) {
+                *last_seen = Instant::now();
+            }
+        }
+        // order codes top-to-bottom, left-to-right so up/down navigation follows the frame
+        qrcodes.sort_by_key(|code| (code.position().top_left.y, code.position().top_left.x));
+        self.qrcodes = qrcodes;
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        let selected = self.selected.as_deref()?;
+        self.qrcodes.iter().position(|code| code.text() == selected)
+    }
+
+    /// Codes to box and list with their index in `qrcodes`, all of them unless in newest only mode.
+    fn shown_codes(&self) -> impl Iterator<Item = (usize, &QRCode)> {
+        let newest_only = self.settings.newest_only;
+        let newest = self.latest.as_ref().map(|(text, _)| text.as_str());
+        self.qrcodes
+            .iter()
+            .enumerate()
+            .filter(move |(_, code)| !newest_only || newest == Some(code.text()))
+    }
+
+    /// The code copy/open act on: the selected one if it's still in frame, otherwise the last.
+    fn target_code(&self) -> Option<&QRCode> {
+        match self.selected_index() {
+            Some(index) => self.qrcodes.get(index),
+            None => self.qrcodes.last(),
+        }
+    }
+
+    fn select_next(&mut self, _: &SelectNext, _window: &mut Window, cx: &mut Context<Self>) {
+        if self.qrcodes.is_empty() {
+            return;
+        }
+        let index = match self.selected_index() {
+            Some(index) => (index + 1) % self.qrcodes.len(),
+            None => 0,
+        };
+        self.selected = Some(self.qrcodes[index].text().to_string());
+        cx.notify();
+    }
+
+    fn select_previous(&mut self, _: &SelectPrevious, _window: &mut Window, cx: &mut Context<Self>) {
+        if self.qrcodes.is_empty() {
+            return;
+        }
+        let index = match self.selected_index() {
+            Some(0) | None => self.qrcodes.len() - 1,
+            Some(index) => index - 1,
+        };
+        self.selected = Some(self.qrcodes[index].text().to_string());
+        cx.notify();
+    }
+
+    fn copy_selected(&mut self, _: &CopySelected, _window: &mut Window, cx: &mut Context<Self>) {
+        if let Some(code) = self.target_code() {
+            cx.write_to_clipboard(ClipboardItem::new_string(code.text().to_string()));
+        }
+    }
+
+    /// Copy the Nth most recently scanned code for cmd-N, with cmd-0 the tenth, returning whether the
+    /// keystroke was one of them.
+    fn copy_recent(&mut self, keystroke: &Keystroke, cx: &mut Context<Self>) -> bool {
+        let modifiers = &keystroke.modifiers;
+        if !modifiers.platform || modifiers.shift || modifiers.alt || modifiers.control {
+            return false;
+        }
+        let Ok(digit) = keystroke.key.parse::<usize>() else {
+            return false;
+        };
+        let index = match digit {
+            0 => RECENTS - 1,
+            1..=9 => digit - 1,
+            _ => return false,
+        };
+        match self.history.recent(RECENTS).into_iter().nth(index) {
+            Some(text) => {
+                let message = format!("Copied {}", truncate_for_display(&text, MAX_DISPLAY_CHARS));
+                cx.write_to_clipboard(ClipboardItem::new_string(text));
+                self.show_status(message, cx);
+            }
+            None => self.show_status(format!("Fewer than {} codes scanned", index + 1), cx),
+        }
+        true
+    }
+
+    /// The codes cmd-1 to cmd-0 copy, numbered, while cmd is held.
+    fn recents_overlay(&self) -> Option<impl IntoElement> {
+        if !self.show_recents {
+            return None;
+        }
+        let recent = self.history.recent(RECENTS);
+        if recent.is_empty() {
+            return None;
+        }
+        Some(
+            div()
+                .absolute()
+                .bottom_2()
+                .left_2()
+                .flex()
+                .flex_col()
+                .p_2()
+                .rounded_md()
+                .bg(gpui::hsla(0., 0., 0., 0.75))
+                .text_size(px(12.))
+                .children(recent.iter().enumerate().map(|(index, text)| {
+                    let key = (index + 1) % 10;
+                    format!("⌘{}  {}", key, truncate_for_display(text, MAX_DISPLAY_CHARS))
+                })),
+        )
+    }
+
+    fn dismiss(&mut self, text: &str, cx: &mut Context<Self>) {
+        self.dismissed.insert(text.to_string());
+        self.qrcodes.retain(|code| code.text() != text);
+        cx.notify();
+    }
+
+    /// A green flash over the preview fading out after a new code is scanned, kept off the results so it
+    /// never hides the text.
+    fn scan_flash(&self) -> Option<impl IntoElement> {
+        let (count, started) = self.flash.filter(|_| self.settings.flash_on_scan)?;
+        (started.elapsed() < FLASH_DURATION).then(|| {
+            div().absolute().inset_0().bg(gpui::green()).with_animation(
+                ("scan-flash", count),
+                Animation::new(FLASH_DURATION),
+                |flash, delta| flash.opacity(0.5 * (1. - delta)),
+            )
+        })
+    }
+
+    /// The latest code shown large over the preview, fading out once it has left the frame.
+    fn presentation_overlay(&self, window: &Window) -> Option<impl IntoElement> {
+        if !self.settings.presentation {
+            return None;
+        }
+        let (text, last_seen) = self.latest.as_ref()?;
+        let gone_after = Duration::from_millis(self.settings.decode.gone_after_ms);
+        let since_gone = last_seen.elapsed().checked_sub(gone_after).unwrap_or_default();
+        let opacity = 1. - since_gone.as_secs_f32() / PRESENTATION_FADE.as_secs_f32();
+        if opacity <= 0. {
+            return None;
+        }
+
+        let text = truncate_for_display(text, MAX_DISPLAY_CHARS);
+        // size the font so the text roughly fills the window width, assuming glyphs ~0.6em wide
+        let width = window.viewport_size().width.0 * 0.8;
+        let font_size = (width / (text.chars().count().max(1) as f32 * 0.6)).clamp(24., 120.);
+
+        Some(
+            div()
+                .absolute()
+                .inset_0()
+                .flex()
+                .items_center()
+                .justify_center()
+                .opacity(opacity)
+                .child(
+                    div()
+                        .max_w(px(width))
+                        .p_4()
+                        .rounded_lg()
+                        .bg(gpui::hsla(0., 0., 0., 0.6))
+                        .text_size(px(font_size))
+                        .child(text),
+                ),
+        )
+    }
+
+    /// Step the lock on zoom towards the single code in frame, or back out to the whole frame once
+    /// it has gone, returning the region of the frame to show.
+    fn update_zoom(&mut self) -> Option<FrameRegion> {
+        let frame_size = self.frame_size.filter(|_| self.lock_on)?;
+        let full = FrameRegion::full(frame_size);
+        let target = match self.qrcodes.as_slice() {
+            [code] => FrameRegion::around(code.position(), frame_size),
+            _ => full,
+        };
+        let zoom = self.zoom.unwrap_or(full).approach(target, LOCK_ON_SMOOTHING);
+        self.zoom = Some(zoom).filter(|zoom| target != full || !zoom.close_to(&full));
+        self.zoom
+    }
+
+    /// The right hand pane of the split view, the latest code regenerated from its text.
+    fn regenerated_pane(&mut self, window: &mut Window) -> Option<impl IntoElement> {
+        if !self.settings.split_view {
+            return None;
+        }
+        let latest = self.latest.as_ref().map(|(text, _)| text);
+        if latest.is_some_and(|text| {
+            self.regenerated
+                .as_ref()
+                .is_none_or(|regenerated| &regenerated.text != text)
+        }) {
+            let regenerated = Regenerated::new(latest.unwrap().clone(), 512, round_trips);
+            if let Some(image) = self.regenerated.replace(regenerated).and_then(|old| old.image) {
+                self.retired_images.push(image);
+                self.drop_retired_images(window);
+            }
+        }
+
+        let pane = div()
+            .flex_1()
+            .h_full()
+            .flex()
+            .flex_col()
+            .items_center()
+            .justify_center()
+            .p_4()
+            .gap_2();
+        Some(match self.regenerated.as_ref() {
+            Some(regenerated) => {
+                let label = match (&regenerated.image, regenerated.round_trips) {
+                    (None, _) => "Too long to encode as a QR code",
+                    (Some(_), true) => "Regenerated code decodes to the same text",
+                    (Some(_), false) => "Regenerated code does not decode to the same text",
+                };
+                pane.children(regenerated.image.clone().map(|image| {
+                    img(ImageSource::Render(image))
+                        .w_full()
+                        .flex_1()
+                        .object_fit(gpui::ObjectFit::Contain)
+                }))
+                .child(label)
+            }
+            None => pane.child("Scan a code to regenerate it"),
+        })
+    }
+
+    /// Regenerate each code in frame for round trip verification, forgetting codes that have left the
+    /// frame, or all of them once verification is turned off.
+    fn update_verified(&mut self, window: &mut Window) {
+        let enabled = self.settings.verify_round_trip;
+        let qrcodes = &self.qrcodes;
+        let mut retired = Vec::new();
+        self.verified.retain(|text, regenerated| {
+            let keep = enabled && qrcodes.iter().any(|code| code.text() == text);
+            if !keep {
+                retired.extend(regenerated.image.take());
+            }
+            keep
+        });
+        if enabled {
+            for code in &self.qrcodes {
+                if !self.verified.contains_key(code.text()) {
+                    let regenerated = Regenerated::new(code.text().to_string(), VERIFY_SIZE, |_, grey| {
+                        round_trips_bytes(code.bytes(), grey)
+                    });
+                    self.verified.insert(code.text().to_string(), regenerated);
+                }
+            }
+        }
+        if !retired.is_empty() {
+            self.retired_images.extend(retired);
+            self.drop_retired_images(window);
+        }
+    }
+
+    /// Read the camera's exposure for the log panel, at most once per poll interval.
+    fn poll_exposure(&mut self) {
+        if !self.show_logs {
+            self.exposure = None;
+            return;
+        }
+        if self
+            .exposure
+            .is_some_and(|(_, read)| read.elapsed() < EXPOSURE_POLL_INTERVAL)
+        {
+            return;
+        }
+        self.exposure = self
+            .capture
+            .as_ref()
+            .map(|capture| (capture.exposure(), Instant::now()));
+    }
+
+    /// Edit the history filter with a key press while the history panel is shown, returning whether the
+    /// key was used.
+    fn edit_history_filter(&mut self, keystroke: &Keystroke, cx: &mut Context<Self>) -> bool {
+        let Some(filter) = self.history_filter.as_mut() else {
+            return false;
+        };
+        if keystroke.modifiers.platform || keystroke.modifiers.control {
+            return false;
+        }
+        match (keystroke.key.as_str(), keystroke.key_char.as_deref()) {
+            ("backspace", _) => {
+                filter.pop();
+            }
+            ("escape", _) => filter.clear(),
+            (_, Some(key_char)) if !key_char.chars().any(char::is_control) => filter.push_str(key_char),
+            _ => return false,
+        }
+        cx.notify();
+        true
+    }
+
+    /// Codes scanned this session, newest first, filtered as typed while the panel is shown.
+    fn history_panel(&self) -> Option<impl IntoElement> {
+        let filter = self.history_filter.as_ref()?;
+        let entries = self.history.filtered(filter);
+        let rows = entries
+            .iter()
+            .rev()
+            .take(HISTORY_PANEL_ROWS)
+            .map(|entry| match &entry.fields {
+                Some(fields) => truncate_for_display(&fields.to_string(), MAX_DISPLAY_CHARS),
+                None => truncate_for_display(&entry.text, MAX_DISPLAY_CHARS),
+            });
+        Some(
+            div()
+                .flex()
+                .flex_col()
+                .p_2()
+                .bg(gpui::rgb(0x202020))
+                .child(format!(
+                    "History filter: {}▏ ({} of {})",
+                    escape_control(filter),
+                    entries.len(),
+                    self.history.len()
+                ))
+                .children(rows),
+        )
+    }
+
+    fn ui_interval(&self) -> Duration {
+        Duration::from_millis(self.settings.ui_interval_ms.unwrap_or(DEFAULT_UI_INTERVAL_MS))
+    }
+
+    /// Step the decode thread's sleep between frames by `delta` milliseconds, trading latency for CPU.
+    fn step_decode_interval(&mut self, delta: i64, cx: &mut Context<Self>) {
+        let interval = step_interval(self.settings.decode.decode_interval_ms, delta);
+        self.update_settings(|settings| settings.decode.decode_interval_ms = interval);
+        self.decoder.set_options(DecodeOptions {
+            decode_interval_ms: interval,
+            ..self.decoder.options()
+        });
+        cx.notify();
+    }
+
+    /// Step how often the window checks for new frames by `delta` milliseconds.
+    fn step_ui_interval(&mut self, delta: i64, cx: &mut Context<Self>) {
+        let interval = step_interval(self.ui_interval().as_millis() as u64, delta);
+        self.update_settings(|settings| settings.ui_interval_ms = Some(interval));
+        cx.notify();
+    }
+
+    /// A row showing an interval with buttons to step it down and up.
+    fn interval_stepper(
+        &self,
+        label: &'static str,
+        interval_ms: u64,
+        step: fn(&mut Self, i64, &mut Context<Self>),
+        cx: &mut Context<Self>,
+    ) -> impl IntoElement {
+        let button = |text: &'static str, delta: i64| {
+            div()
+                .id((label, usize::from(delta > 0)))
+                .px_1()
+                .rounded_sm()
+                .bg(gpui::rgb(0x404040))
+                .cursor_pointer()
+                .child(text)
+                .on_click(cx.listener(move |view, _, _, cx| step(view, delta, cx)))
+        };
+        div()
+            .flex()
+            .gap_1()
+            .child(format!("{} every {}ms", label, interval_ms))
+            .child(button("-", -(INTERVAL_STEP_MS as i64)))
+            .child(button("+", INTERVAL_STEP_MS as i64))
+    }
+
+    fn log_panel(&self, cx: &mut Context<Self>) -> Option<impl IntoElement> {
+        if !self.show_logs {
+            return None;
+        }
+        // the camera falls back to zxing-cpp when its detector can't find every enabled format
+        let backend = match (self.decoder.options().backend, self.settings.decode.backend) {
+            (Backend::Native, _) => "camera's detector",
+            (Backend::Zxing, Backend::Native) => "zxing-cpp, the camera's detector is unavailable",
+            (Backend::Zxing, Backend::Zxing) => "zxing-cpp",
+        };
+        Some(
+            div()
+                .flex()
+                .flex_col()
+                .p_2()
+                .bg(gpui::rgb(0x202020))
+                .text_size(px(11.))
+                .font_family("Menlo")
+                .child(format!(
+                    "{}, {} dropped frames",
+                    self.frame_rate.label(),
+                    self.decoder.dropped_frames()
+                ))
+                .child(format!("Decoding with {}", backend))
+                .child(self.decoder.stats().summary().to_string())
+                .child(self.interval_stepper(
+                    "decode",
+                    self.settings.decode.decode_interval_ms,
+                    Self::step_decode_interval,
+                    cx,
+                ))
+                .child(self.interval_stepper(
+                    "refresh",
+                    self.ui_interval().as_millis() as u64,
+                    Self::step_ui_interval,
+                    cx,
+                ))
+                .children(self.exposure.map(|(exposure, _)| exposure.to_string()))
+                .children(
+                    self.capture
+                        .as_ref()
+                        .and_then(DeviceCapture::pixel_format)
+                        .map(|format| format!("{} pixel format", format)),
+                )
+                .children(self.logs.tail(LOG_PANEL_LINES)),
+        )
+    }
+
+    fn debug_panel(&self, cx: &mut Context<Self>) -> Option<impl IntoElement> {
+        let panel = self.debug.as_ref()?;
+        let toggle = |id: SharedString, enabled: bool, update: Box<dyn Fn(&mut DecodeOptions)>| {
+            div()
+                .id(id.clone())
+                .px_1()
+                .rounded_sm()
+                .bg(if enabled {
+                    gpui::blue()
+                } else {
+                    gpui::rgb(0x404040).into()
+                })
+                .cursor_pointer()
+                .child(id)
+                .on_click(cx.listener(move |view, _, _, cx| {
+                    if let Some(panel) = view.debug.as_mut() {
+                        panel.update_options(&update);
+                    }
+                    cx.notify();
+                }))
+        };
+        let options = &panel.options;
+        let mut toggles = vec![
+            toggle(
+                "try harder".into(),
+                options.try_harder,
+                Box::new(|options| options.try_harder = !options.try_harder),
+            ),
+            toggle(
+                "try invert".into(),
+                options.try_invert,
+                Box::new(|options| options.try_invert = !options.try_invert),
+            ),
+            toggle(
+                "deskew".into(),
+                options.deskew,
+                Box::new(|options| options.deskew = !options.deskew),
+            ),
+            toggle(
+                "normalize contrast".into(),
+                options.normalize_contrast,
+                Box::new(|options| options.normalize_contrast = !options.normalize_contrast),
+            ),
+        ];
+        toggles.extend(CodeFormat::ALL.into_iter().map(|format| {
+            toggle(
+                format.barcode_format().to_string().into(),
+                options.formats.contains(&format),
+                Box::new(move |options| match options.formats.iter().position(|f| *f == format) {
+                    Some(index) => {
+                        options.formats.remove(index);
+                    }
+                    None => options.formats.push(format),
+                }),
+            )
+        }));
+        let results = match &panel.results {
+            Ok(codes) if codes.is_empty() => "No codes found".to_string(),
+            Ok(codes) => codes.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+            Err(err) => format!("Decode failed: {}", err),
+        };
+
+        Some(
+            div()
+                .flex()
+                .flex_col()
+                .gap_1()
+                .p_2()
+                .bg(gpui::rgb(0x202020))
+                .child(
+                    div().flex().flex_wrap().gap_1().children(toggles).child(
+                        div()
+                            .id("recapture")
+                            .px_1()
+                            .rounded_sm()
+                            .bg(gpui::rgb(0x404040))
+                            .cursor_pointer()
+                            .child("Recapture")
+                            .on_click(cx.listener(|view, _, _, cx| {
+                                if let Some(frame) = view.decoder.last_frame() {
+                                    let options = view.debug.take().map(|panel| panel.options);
+                                    view.debug = Some(DebugPanel::new(frame, options.unwrap_or_default()));
+                                }
+                                cx.notify();
+                            })),
+                    ),
+                )
+                .child(results),
+        )
+    }
+
+    fn result_row(&self, index: usize, code: &QRCode, selected: bool, cx: &mut Context<Self>) -> impl IntoElement {
+        let text = code.text().to_string();
+        let button = |label: &'static str| {
+            div()
+                .id((label, index))
+                .px_1()
+                .rounded_sm()
+                .bg(gpui::rgb(0x404040))
+                .text_color(gpui::white())
+                .cursor_pointer()
+                .child(label)
+        };
+        let copy_text = text.clone();
+        // other schemes could launch arbitrary apps, see `scan_action::is_web_url`
+        let open_text = scan_action::is_web_url(&text).then(|| text.trim().to_string());
+
+        div()
+            .flex()
+            .gap_2()
+            .px_2()
+            .when(selected, |row| row.bg(gpui::blue()))
+            .when(self.settings.high_contrast, |row| {
+                row.py_1()
+                    .text_size(px(24.))
+                    .font_weight(FontWeight::BOLD)
+                    .text_color(gpui::black())
+                    .bg(if selected { gpui::yellow() } else { gpui::white() })
+            })
+            .child(
+                self.extractor
+                    .as_ref()
+                    .and_then(|extractor| extractor.extract(code.text()))
+                    .map_or_else(|| code.to_string(), |fields| fields.to_string()),
+            )
+            .children(self.products.get(code.text()).cloned().flatten())
+            .children(self.verified.get(code.text()).map(|regenerated| {
+                div()
+                    .flex()
+                    .items_center()
+                    .gap_1()
+                    .children(
+                        regenerated
+                            .image
+                            .clone()
+                            .map(|image| img(ImageSource::Render(image)).size(px(32.))),
+                    )
+                    .child(if regenerated.round_trips { "✓" } else { "✗" })
+            }))
+            .child(button("Copy").on_click(cx.listener(move |_, _, _, cx| {
+                cx.write_to_clipboard(ClipboardItem::new_string(copy_text.clone()));
+            })))
+            .children(open_text.map(|open_text| {
+                button("Open").on_click(cx.listener(move |_, _, _, cx| {
+                    cx.open_url(&open_text);
+                }))
+            }))
+            .child(button("Dismiss").on_click(cx.listener(move |view, _, _, cx| {
+                view.dismiss(&text, cx);
+            })))
+    }
+
+    fn cycle_rotation(&mut self, _: &CycleRotation, _window: &mut Window, cx: &mut Context<Self>) {
+        let rotation = self.decoder.rotation().next();
+        self.decoder.set_rotation(rotation);
+        self.update_settings(|settings| settings.rotation = rotation);
+        cx.notify();
+    }
+
+    fn toggle_notifications(&mut self, _: &ToggleNotifications, _window: &mut Window, cx: &mut Context<Self>) {
+        let notify = !self.settings.notify;
+        self.update_settings(|settings| settings.notify = notify);
+        cx.notify();
+    }
+
+    fn toggle_flip_vertical(&mut self, _: &ToggleFlipVertical, _window: &mut Window, cx: &mut Context<Self>) {
+        let flip = !self.decoder.flip_vertical();
+        self.decoder.set_flip_vertical(flip);
+        self.update_settings(|settings| settings.flip_vertical = flip);
+        cx.notify();
+    }
+
+    fn toggle_preview_fit(&mut self, _: &TogglePreviewFit, _window: &mut Window, cx: &mut Context<Self>) {
+        let preview_fit = self.settings.preview_fit.toggled();
+        self.update_settings(|settings| settings.preview_fit = preview_fit);
+        cx.notify();
+    }
+
+    fn toggle_mirror_preview(&mut self, _: &ToggleMirrorPreview, _window: &mut Window, cx: &mut Context<Self>) {
+        let preview = !self.settings.mirror.preview;
+        self.update_settings(|settings| settings.mirror.preview = preview);
+        self.decoder.set_mirror(self.settings.mirror);
+        cx.notify();
+    }
+
+    fn toggle_mirror_decode(&mut self, _: &ToggleMirrorDecode, _window: &mut Window, cx: &mut Context<Self>) {
+        let decode = !self.settings.mirror.decode;
+        self.update_settings(|settings| settings.mirror.decode = decode);
+        self.decoder.set_mirror(self.settings.mirror);
+        cx.notify();
+    }
+
+    fn toggle_flash_on_scan(&mut self, _: &ToggleFlashOnScan, _window: &mut Window, cx: &mut Context<Self>) {
+        let flash_on_scan = !self.settings.flash_on_scan;
+        self.update_settings(|settings| settings.flash_on_scan = flash_on_scan);
+        cx.notify();
+    }
+
+    fn toggle_presentation(&mut self, _: &TogglePresentation, _window: &mut Window, cx: &mut Context<Self>) {
+        let presentation = !self.settings.presentation;
+        self.update_settings(|settings| settings.presentation = presentation);
+        cx.notify();
+    }
+
+    fn toggle_recording(&mut self, _: &ToggleRecording, _window: &mut Window, cx: &mut Context<Self>) {
+        if self.record_path.is_none() {
+            self.show_status("Start with --record <PATH> to record", cx);
+            return;
+        }
+        match self.capture.as_ref() {
+            Some(capture) if capture.is_recording() => {
+                capture.stop_recording();
+                cx.notify();
+            }
+            Some(_) => self.start_recording(cx),
+            None => self.show_status("Camera not running", cx),
+        }
+    }
+
+    fn reset_tally(&mut self, _: &ResetTally, _window: &mut Window, cx: &mut Context<Self>) {
+        if let Some(tally) = &self.tally {
+            tally.reset();
+            self.show_status("Tally reset", cx);
+        }
+    }
+
+    fn tally_list(&self) -> Option<impl IntoElement> {
+        let counts = self.tally.as_ref()?.counts();
+        Some(
+            div().flex().flex_col().px_2().children(
+                counts
+                    .into_iter()
+                    .map(|(text, count)| format!("{} × {}", count, truncate_for_display(&text, MAX_DISPLAY_CHARS))),
+            ),
+        )
+    }
+
+    fn toggle_always_on_top(&mut self, _: &ToggleAlwaysOnTop, _window: &mut Window, cx: &mut Context<Self>) {
+        let always_on_top = !self.settings.always_on_top;
+        self.update_settings(|settings| settings.always_on_top = always_on_top);
+        cx.notify();
+    }
+
+    fn toggle_high_contrast(&mut self, _: &ToggleHighContrast, _window: &mut Window, cx: &mut Context<Self>) {
+        let high_contrast = !self.settings.high_contrast;
+        self.update_settings(|settings| settings.high_contrast = high_contrast);
+        cx.notify();
+    }
+
+    fn toggle_verify_round_trip(&mut self, _: &ToggleVerifyRoundTrip, _window: &mut Window, cx: &mut Context<Self>) {
+        let verify_round_trip = !self.settings.verify_round_trip;
+        self.update_settings(|settings| settings.verify_round_trip = verify_round_trip);
+        cx.notify();
+    }
+
+    fn toggle_newest_only(&mut self, _: &ToggleNewestOnly, _window: &mut Window, cx: &mut Context<Self>) {
+        let newest_only = !self.settings.newest_only;
+        self.update_settings(|settings| settings.newest_only = newest_only);
+        cx.notify();
+    }
+
+    fn toggle_lock_on(&mut self, _: &ToggleLockOn, _window: &mut Window, cx: &mut Context<Self>) {
+        self.lock_on = !self.lock_on;
+        self.zoom = None;
+        let message = if self.lock_on {
+            "Lock on enabled"
+        } else {
+            "Lock on disabled"
+        };
+        self.show_status(message, cx);
+    }
+
+    fn toggle_corners(&mut self, _: &ToggleCorners, _window: &mut Window, cx: &mut Context<Self>) {
+        self.show_corners = !self.show_corners;
+        cx.notify();
+    }
+
+    fn toggle_box_labels(&mut self, _: &ToggleBoxLabels, _window: &mut Window, cx: &mut Context<Self>) {
+        let label_boxes = !self.settings.overlay.label_boxes;
+        self.update_settings(|settings| settings.overlay.label_boxes = label_boxes);
+        cx.notify();
+    }
+
+    fn toggle_calibration(&mut self, _: &ToggleCalibration, _window: &mut Window, cx: &mut Context<Self>) {
+        self.calibrating = !self.calibrating;
+        cx.notify();
+    }
+
+    fn toggle_pause_decode(&mut self, _: &TogglePauseDecode, _window: &mut Window, cx: &mut Context<Self>) {
+        let paused = !self.decoder.decode_paused();
+        self.decoder.set_decode_paused(paused);
+        self.show_status(if paused { "Decoding paused" } else { "Decoding resumed" }, cx);
+    }
+
+    /// A single row along the bottom of the window: the camera, or what it's doing if it isn't
+    /// running, any status message, then the frame rate, how many codes are in frame and icons for
+    /// decoding being paused and the preview being mirrored.
+    fn status_bar(&self, camera: SharedString, status: Option<SharedString>) -> impl IntoElement {
+        let icon = |label: &'static str, color: gpui::Hsla| div().text_color(color).child(label);
+        div()
+            .flex()
+            .flex_none()
+            .items_center()
+            .gap_3()
+            .w_full()
+            .h(px(22.))
+            .px_2()
+            .bg(gpui::rgb(0x202020))
+            .text_size(px(12.))
+            .whitespace_nowrap()
+            .overflow_hidden()
+            .child(camera)
+            .child(
+                div()
+                    .flex_1()
+                    .overflow_hidden()
+                    .text_color(gpui::rgb(0xc0c0c0))
+                    .children(status),
+            )
+            .child(self.frame_rate.label())
+            .child(match self.qrcodes.len() {
+                1 => "1 code".to_string(),
+                count => format!("{} codes", count),
+            })
+            .children(self.decoder.decode_paused().then(|| icon("❚❚", gpui::yellow())))
+            .children(self.decoder.mirror().preview.then(|| icon("⇆", gpui::white())))
+    }
+
+    /// A crosshair over the centre of the preview and a readout of the largest code's size, with which
+    /// way to move the camera for its modules to be the calibrated size, for setting up a fixed station.
+    fn calibration_overlay(&self) -> Option<impl IntoElement> {
+        if !self.calibrating {
+            return None;
+        }
+        let calibration = &self.settings.calibration;
+        let readout = match self.qrcodes.iter().max_by(|a, b| a.area().total_cmp(&b.area())) {
+            Some(code) => {
+                let module_size = calibration.module_size(code.side());
+                format!(
+                    "Code {:.0}px, modules {:.1}px (target {:.1}px): {}",
+                    code.side(),
+                    module_size,
+                    calibration.module_px,
+                    calibration.guidance(module_size)
+                )
+            }
+            None => format!("No code in frame (target {:.1}px modules)", calibration.module_px),
+        };
+        let line = || div().absolute().bg(gpui::hsla(0., 0., 1., 0.6));
+        Some(
+            div()
+                .absolute()
+                .inset_0()
+                .child(line().left(relative(0.5)).top_0().h_full().w(px(1.)))
+                .child(line().top(relative(0.5)).left_0().w_full().h(px(1.)))
+                .child(
+                    div()
+                        .absolute()
+                        .top_2()
+                        .left_2()
+                        .px_2()
+                        .rounded_md()
+                        .bg(gpui::hsla(0., 0., 0., 0.6))
+                        .child(readout),
+                ),
+        )
+    }
+
+    /// Each shown code's text just above its box, or inside the top of the box where there's no room
+    /// above, sized to the box. Positioned from where the preview was last painted, empty before that.
+    fn box_labels(&self, zoom: Option<FrameRegion>) -> Vec<impl IntoElement> {
+        let Some(bounds) = self.preview_bounds.get() else {
+            return Vec::new();
+        };
+        let preview_fit = self.settings.preview_fit;
+        self.shown_codes()
+            .map(|(_, code)| {
+                let (width, height) = code.frame_size();
+                let transform = match zoom {
+                    Some(region) => FrameTransform::cover_region(bounds, region),
+                    None => FrameTransform::fit(bounds, width, height, preview_fit),
+                };
+                let code_box = transform.bounding_box(code.position());
+                let font_size = px((code_box.size.height.0 * 0.2).clamp(10., 28.));
+                let line_height = font_size * 1.3;
+                let top = match code_box.origin.y - line_height >= bounds.origin.y {
+                    true => code_box.origin.y - line_height,
+                    false => code_box.origin.y,
+                };
+                div()
+                    .absolute()
+                    .left(code_box.origin.x - bounds.origin.x)
+                    .top(top - bounds.origin.y)
+                    .w(px(code_box.size.width.0.max(48.)))
+                    .px_1()
+                    .bg(gpui::hsla(0., 0., 0., 0.6))
+                    .text_size(font_size)
+                    .line_height(line_height)
+                    .whitespace_nowrap()
+                    .overflow_hidden()
+                    .text_ellipsis()
+                    .child(truncate_for_display(code.text(), MAX_DISPLAY_CHARS))
+            })
+            .collect()
+    }
+
+    fn toggle_history(&mut self, _: &ToggleHistory, _window: &mut Window, cx: &mut Context<Self>) {
+        self.history_filter = match self.history_filter {
+            Some(_) => None,
+            None => Some(String::new()),
+        };
+        cx.notify();
+    }
+
+    fn toggle_log_panel(&mut self, _: &ToggleLogPanel, _window: &mut Window, cx: &mut Context<Self>) {
+        self.show_logs = !self.show_logs;
+        cx.notify();
+    }
+
+    fn toggle_debug_panel(&mut self, _: &ToggleDebugPanel, _window: &mut Window, cx: &mut Context<Self>) {
+        if self.debug.take().is_none() {
+            match self.decoder.last_frame() {
+                Some(frame) => self.debug = Some(DebugPanel::new(frame, self.decoder.options())),
+                None => self.show_status("No frame to debug yet", cx),
+            }
+        }
+        cx.notify();
+    }
+
+    fn toggle_split_view(&mut self, _: &ToggleSplitView, _window: &mut Window, cx: &mut Context<Self>) {
+        let split_view = !self.settings.split_view;
+        self.update_settings(|settings| settings.split_view = split_view);
+        cx.notify();
+    }
+
+    fn decode_clipboard(&mut self, _: &DecodeClipboard, _window: &mut Window, cx: &mut Context<Self>) {
+        let image = cx.read_from_clipboard().and_then(|item| {
+            item.entries().iter().find_map(|entry| match entry {
+                ClipboardEntry::Image(image) => Some(image.clone()),
+                _ => None,
+            })
+        });
+        let message = match image.map(|image| image::load_from_memory(&image.bytes)) {
+            None => "No image on the clipboard".to_string(),
+            Some(Err(err)) => format!("Unable to read clipboard image: {}", err),
+            Some(Ok(image)) => match self.decoder.decode_gray(&image.to_luma8()) {
+                Ok(qrcodes) if qrcodes.is_empty() => "No code found in clipboard image".to_string(),
+                Ok(qrcodes) => {
+                    self.on_new_qrcodes(&qrcodes, cx);
+                    let texts: Vec<String> = qrcodes
+                        .iter()
+                        .map(|code| truncate_for_display(code.text(), MAX_DISPLAY_CHARS))
+                        .collect();
+                    format!("Clipboard: {}", texts.join(", "))
+                }
+                Err(err) => format!("Failed to decode clipboard image: {}", err),
+            },
+        };
+        self.show_status(message, cx);
+    }
+
+    /// Save the frame the decoder last saw as a PNG, with what it decodes to embedded.
+    fn save_frame(&mut self, _: &SaveFrame, _window: &mut Window, cx: &mut Context<Self>) {
+        let Some(frame) = self.decoder.last_frame() else {
+            self.show_status("No frame to save", cx);
+            return;
+        };
+        let Some(dir) = self.settings.frames_dir.clone().or_else(snapshot::default_dir) else {
+            self.show_status("Unable to determine where to save frames", cx);
+            return;
+        };
+        // decoded again so the positions are exactly those in the saved pixels
+        let qrcodes = self.decoder.decode_gray(&frame).unwrap_or_else(|err| {
+            log::warn!("failed to decode saved frame: {}", err);
+            Vec::new()
+        });
+        let message = match snapshot::save_frame_in(&dir, &frame, &qrcodes) {
+            Ok(path) => format!("Saved {}", path.display()),
+            Err(err) => {
+                log::error!("failed to save frame: {:?}", err);
+                format!("Failed to save frame: {}", err)
+            }
+        };
+        self.show_status(message, cx);
+    }
+
+    fn drop_files(&mut self, paths: &ExternalPaths, window: &mut Window, cx: &mut Context<Self>) {
+        self.dropped_queue.extend(paths.paths().iter().cloned());
+        if self.dropped.is_none() {
+            self.show_next_dropped(window, cx);
+        }
+    }
+
+    /// Decode the next dropped file and show it in place of the preview, skipping files that can't be
+    /// read with a status message.
+    fn show_next_dropped(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        if let Some(dropped) = self.dropped.take() {
+            self.retired_images.push(dropped.image);
+            self.drop_retired_images(window);
+        }
+        while let Some(path) = self.dropped_queue.pop_front() {
+            let name = path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy()
+                .into_owned();
+            let image = match image::open(&path) {
+                Ok(image) => image,
+                Err(err) => {
+                    self.show_status(format!("Unable to read {}: {}", name, err), cx);
+                    continue;
+                }
+            };
+            self.show_image(name, image.into_rgba8(), window, cx);
+            break;
+        }
+        cx.notify();
+    }
+
+    /// Show an image in place of the preview, in place of any already shown, and decode it.
+    fn show_image(&mut self, name: String, rgba: RgbaImage, window: &mut Window, cx: &mut Context<Self>) {
+        if let Some(dropped) = self.dropped.take() {
+            self.retired_images.push(dropped.image);
+            self.drop_retired_images(window);
+        }
+        self.dropped = Some(DroppedImage {
+            name,
+            image: render_image(rgba.clone()),
+            rgba,
+            qrcodes: Vec::new(),
+            reported: HashSet::new(),
+            shown: Instant::now(),
+        });
+        self.decode_dropped(cx);
+    }
+
+    /// Capture a still at the camera's full photo resolution and show it decoded like a dropped image,
+    /// for codes too small for the video frames to resolve.
+    fn capture_still(&mut self, _: &CaptureStill, window: &mut Window, cx: &mut Context<Self>) {
+        let Some(capture) = self.capture.as_mut() else {
+            self.show_status("Camera not running", cx);
+            return;
+        };
+        let receiver = match capture.capture_photo() {
+            Ok(receiver) => receiver,
+            Err(err) => {
+                self.show_status(format!("Failed to capture still: {}", err), cx);
+                return;
+            }
+        };
+        self.show_status("Capturing still...", cx);
+        cx.spawn_in(window, async move |view, cx| {
+            let photo = cx
+                .background_spawn(async move {
+                    receiver
+                        .recv_timeout(STILL_TIMEOUT)
+                        .unwrap_or_else(|_| Err("timed out".to_string()))
+                })
+                .await;
+            view.update_in(cx, |view, window, cx| match photo {
+                Ok(rgba) => {
+                    let name = format!("Still {}x{}", rgba.width(), rgba.height());
+                    view.show_image(name, rgba, window, cx);
+                }
+                Err(err) => view.show_status(format!("Failed to capture still: {}", err), cx),
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    /// Decode the dropped image being shown, boxing the codes found over it and showing it for another
+    /// `DROPPED_DURATION`. Codes are only recorded as scans the first time they're found in the image.
+    fn decode_dropped(&mut self, cx: &mut Context<Self>) {
+        let Some(dropped) = self.dropped.as_mut() else {
+            return;
+        };
+        let result = self.decoder.decode_gray(&grayscale(&dropped.rgba));
+        dropped.qrcodes = result.as_ref().map(Vec::clone).unwrap_or_default();
+        dropped.shown = Instant::now();
+        let message = match result {
+            Ok(qrcodes) if qrcodes.is_empty() => format!("No code found in {}", dropped.name),
+            Ok(qrcodes) => {
+                let texts: Vec<String> = qrcodes
+                    .iter()
+                    .map(|code| truncate_for_display(code.text(), MAX_DISPLAY_CHARS))
+                    .collect();
+                let message = format!("{}: {}", dropped.name, texts.join(", "));
+                let new_qrcodes: Vec<QRCode> = qrcodes
+                    .into_iter()
+                    .filter(|code| dropped.reported.insert(code.text().to_string()))
+                    .collect();
+                self.on_new_qrcodes(&new_qrcodes, cx);
+                message
+            }
+            Err(err) => format!("Failed to decode {}: {}", dropped.name, err),
+        };
+        self.show_status(message, cx);
+        // render again once it expires, even if the camera is idle
+        cx.spawn(async move |view, cx| {
+            Timer::after(DROPPED_DURATION).await;
+            view.update(cx, |_, cx| cx.notify()).ok();
+        })
+        .detach();
+    }
+
+    /// Decode the image shown in place of the preview again, the live preview is decoded continuously.
+    fn decode_current(&mut self, _: &DecodeCurrent, _window: &mut Window, cx: &mut Context<Self>) {
+        match self.dropped.is_some() {
+            true => self.decode_dropped(cx),
+            false => self.show_status("Drop an image on the window to decode it", cx),
+        }
+    }
+
+    /// Go back to the live preview, forgetting any dropped files still to show.
+    fn dismiss_dropped(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        if self.dropped.is_some() {
+            self.dropped_queue.clear();
+            self.show_next_dropped(window, cx);
+        }
+    }
+
+    fn show_status(&mut self, message: impl Into<SharedString>, cx: &mut Context<Self>) {
+        self.status = Some((message.into(), Instant::now()));
+        cx.notify();
+    }
+
+    fn toggle_threshold(&mut self, _: &ToggleThreshold, _window: &mut Window, cx: &mut Context<Self>) {
+        self.decoder.set_show_threshold(!self.decoder.show_threshold());
+        cx.notify();
+    }
+
+    fn toggle_focus_lock(&mut self, _: &ToggleFocusLock, _window: &mut Window, cx: &mut Context<Self>) {
+        let Some(capture) = self.capture.as_ref() else {
+            return;
+        };
+        let focus = match self.focus {
+            FocusMode::Auto => FocusMode::Locked,
+            FocusMode::Locked | FocusMode::Manual(_) => FocusMode::Auto,
+        };
+        match capture.set_focus(focus) {
+            Ok(()) => self.focus = focus,
+            Err(err) => log::warn!("failed to change focus: {}", err),
+        }
+        cx.notify();
+    }
+
+    /// Apply a change made in the window to the settings in use and save it, through the copy shared by
+    /// every pane.
+    fn update_settings(&mut self, update: impl Fn(&mut Settings)) {
+        update(&mut self.settings);
+        let mut saved_settings = self.saved_settings.borrow_mut();
+        update(&mut saved_settings);
+        if let Err(err) = saved_settings.save() {
+            log::error!("failed to save settings: {:?}", err);
+        }
+    }
+
+    fn open_selected(&mut self, _: &OpenSelected, _window: &mut Window, cx: &mut Context<Self>) {
+        match self.target_code() {
+            Some(code) if scan_action::is_web_url(code.text()) => cx.open_url(code.text().trim()),
+            Some(_) => self.show_status("Only http and https links can be opened", cx),
+            None => {}
+        }
+    }
 }
 
 impl Render for ImageDisplay {
     fn render(&mut self, window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
         self.start(window, cx);
 
+        // a floating level would keep a fullscreen window above other spaces, so it only applies windowed
+        let on_top = self.settings.always_on_top && !window.is_fullscreen();
+        if self.on_top_applied != Some(on_top) {
+            set_always_on_top(window, on_top);
+            self.on_top_applied = Some(on_top);
+        }
+
         let image_data = if let Some(qr_img) = self.img.take() {
-            let frame = Frame::new(qr_img);
-            let image_render = Arc::new(RenderImage::new(vec![frame]));
+            let frame_size = qr_img.dimensions();
+            if self.frame_size.is_some_and(|size| size != frame_size) {
+                // the resolution changed mid-stream, discard the stale frame and overlays
+                self.reset_frame(window);
+            }
+            self.frame_size = Some(frame_size);
+            if self.settings.overlay.auto_contrast {
+                self.box_luma = self
+                    .qrcodes
+                    .iter()
+                    .filter_map(|code| Some((code.text().to_string(), average_luma(&qr_img, code.position())?)))
+                    .collect();
+            }
+            let image_render = render_image(qr_img);
             if let Some(last_image) = self.last_image.replace(image_render.clone()) {
-                window.drop_image(last_image).unwrap();
+                self.retired_images.push(last_image);
             }
+            self.drop_retired_images(window);
             ImageSource::Render(image_render)
         } else if let Some(last_image) = self.last_image.as_ref() {
             ImageSource::Render(last_image.clone())
@@ -90,12 +1802,181 @@ impl Render for ImageDisplay {
             ImageSource::Image(gpui::Image::empty().into())
         };
 
-        let text = match self.camera.as_ref() {
-            Some(text) => text.clone(),
-            None => "Loading...".into(),
+        if self
+            .dropped
+            .as_ref()
+            .is_some_and(|dropped| dropped.shown.elapsed() >= DROPPED_DURATION)
+        {
+            self.show_next_dropped(window, cx);
+        }
+        let showing_dropped = self.dropped.is_some();
+        let image_data = match &self.dropped {
+            Some(dropped) => ImageSource::Render(dropped.image.clone()),
+            None => image_data,
+        };
+
+        let status = self
+            .status
+            .as_ref()
+            .filter(|(_, shown)| shown.elapsed() < STATUS_DURATION)
+            .map(|(message, _)| message.clone());
+        let (text, busy): (SharedString, bool) = match &self.state {
+            CameraState::Discovering => ("Looking for cameras...".into(), true),
+            CameraState::Starting(name) => (format!("Starting {}...", name).into(), true),
+            CameraState::Running(name) => (name.clone(), false),
+            CameraState::Idle => ("Camera paused, press a key or click to resume".into(), false),
+            CameraState::Error(error) => (error.clone(), false),
         };
+        let spinner = busy.then(|| {
+            div()
+                .absolute()
+                .size_full()
+                .flex()
+                .items_center()
+                .justify_center()
+                .text_size(px(48.))
+                .child(div().with_animation(
+                    "camera-spinner",
+                    Animation::new(Duration::from_secs(1)).repeat(),
+                    |spinner, delta| {
+                        let index = (delta * SPINNER_FRAMES.len() as f32) as usize % SPINNER_FRAMES.len();
+                        spinner.child(SPINNER_FRAMES[index])
+                    },
+                ))
+        });
+
+        let selected_index = self.selected_index();
+        let style = &self.settings.overlay;
+        let boxes: Vec<_> = match &self.dropped {
+            Some(dropped) => dropped
+                .qrcodes
+                .iter()
+                .map(|code| (*code.position(), code.frame_size(), style.color(), style.stroke_width))
+                .collect(),
+            None => self
+                .shown_codes()
+                .map(|(index, code)| {
+                    let color = style.color_for(self.box_luma.get(code.text()).copied());
+                    // the selected code gets a heavier box
+                    let stroke_width = match Some(index) == selected_index {
+                        true => style.stroke_width * 2.,
+                        false => style.stroke_width,
+                    };
+                    (*code.position(), code.frame_size(), color, stroke_width)
+                })
+                .collect(),
+        };
+
+        let show_corners = self.show_corners;
+        let preview_bounds = self.preview_bounds.clone();
+        let preview_fit = self.settings.preview_fit;
+        let regenerated = self.regenerated_pane(window);
+
+        let recording_indicator = self.capture.as_ref().is_some_and(DeviceCapture::is_recording).then(|| {
+            div()
+                .absolute()
+                .top_2()
+                .right_2()
+                .px_2()
+                .rounded_md()
+                .bg(gpui::hsla(0., 0., 0., 0.6))
+                .text_color(gpui::red())
+                .child("● REC")
+        });
+
+        let zoom = self.update_zoom().filter(|_| !showing_dropped);
+        // while locked on the frame is painted directly, so it can be scaled beyond the preview bounds
+        let zoomed =
+            zoom.zip(self.last_image.clone())
+                .zip(self.frame_size)
+                .map(|((region, image), (width, height))| {
+                    canvas(
+                        |_, _, _| {},
+                        move |bounds, _, window, _| {
+                            let frame_bounds = FrameTransform::cover_region(bounds, region).frame_bounds(width, height);
+                            window.with_content_mask(Some(ContentMask { bounds }), |window| {
+                                if let Err(err) = window.paint_image(frame_bounds, Corners::default(), image, 0, false)
+                                {
+                                    log::warn!("failed to paint frame: {:?}", err);
+                                }
+                            });
+                        },
+                    )
+                    .size_full()
+                });
+
+        self.update_verified(window);
+        let label_boxes = self.settings.overlay.label_boxes;
+        let labels = match label_boxes {
+            // the live codes aren't in the dropped image
+            true if !showing_dropped => self.box_labels(zoom),
+            _ => Vec::new(),
+        };
+        let results: Vec<_> = self
+            .shown_codes()
+            .filter(|_| !label_boxes)
+            .map(|(index, code)| self.result_row(index, code, Some(index) == selected_index, cx))
+            .collect();
 
         div()
+            .track_focus(&self.focus_handle)
+            .on_key_down(cx.listener(|view, event: &KeyDownEvent, window, cx| {
+                view.wake(cx);
+                // shortcuts act on the dropped image rather than dismissing it
+                if !event.keystroke.modifiers.platform {
+                    view.dismiss_dropped(window, cx);
+                }
+                if view.copy_recent(&event.keystroke, cx) || view.edit_history_filter(&event.keystroke, cx) {
+                    cx.stop_propagation();
+                }
+            }))
+            .on_modifiers_changed(cx.listener(|view, event: &ModifiersChangedEvent, _, cx| {
+                if view.show_recents != event.modifiers.platform {
+                    view.show_recents = event.modifiers.platform;
+                    cx.notify();
+                }
+            }))
+            .on_mouse_down(
+                MouseButton::Left,
+                cx.listener(|view, _, window, cx| {
+                    window.focus(&view.focus_handle);
+                    view.wake(cx);
+                }),
+            )
+            .on_action(cx.listener(Self::select_next))
+            .on_action(cx.listener(Self::select_previous))
+            .on_action(cx.listener(Self::copy_selected))
+            .on_action(cx.listener(Self::open_selected))
+            .on_action(cx.listener(Self::cycle_rotation))
+            .on_action(cx.listener(Self::toggle_threshold))
+            .on_action(cx.listener(Self::toggle_flip_vertical))
+            .on_action(cx.listener(Self::toggle_mirror_preview))
+            .on_action(cx.listener(Self::toggle_mirror_decode))
+            .on_action(cx.listener(Self::toggle_focus_lock))
+            .on_action(cx.listener(Self::toggle_notifications))
+            .on_action(cx.listener(Self::toggle_flash_on_scan))
+            .on_action(cx.listener(Self::toggle_preview_fit))
+            .on_action(cx.listener(Self::toggle_presentation))
+            .on_action(cx.listener(Self::toggle_recording))
+            .on_action(cx.listener(Self::reset_tally))
+            .on_action(cx.listener(Self::toggle_always_on_top))
+            .on_action(cx.listener(Self::toggle_high_contrast))
+            .on_action(cx.listener(Self::toggle_verify_round_trip))
+            .on_action(cx.listener(Self::toggle_newest_only))
+            .on_action(cx.listener(Self::toggle_lock_on))
+            .on_action(cx.listener(Self::toggle_split_view))
+            .on_action(cx.listener(Self::toggle_corners))
+            .on_action(cx.listener(Self::toggle_debug_panel))
+            .on_action(cx.listener(Self::toggle_log_panel))
+            .on_action(cx.listener(Self::toggle_history))
+            .on_action(cx.listener(Self::toggle_box_labels))
+            .on_action(cx.listener(Self::toggle_calibration))
+            .on_action(cx.listener(Self::toggle_pause_decode))
+            .on_action(cx.listener(Self::capture_still))
+            .on_action(cx.listener(Self::decode_current))
+            .on_action(cx.listener(Self::decode_clipboard))
+            .on_action(cx.listener(Self::save_frame))
+            .on_drop(cx.listener(Self::drop_files))
             .size_full()
             .flex()
             .flex_col_reverse()
@@ -103,53 +1984,416 @@ impl Render for ImageDisplay {
             .bg(gpui::black())
             .text_color(gpui::white())
             .items_center()
-            .child(img(image_data).size_full().object_fit(gpui::ObjectFit::Cover))
+            .child(self.status_bar(text, status))
             .child(
-                self.qrcodes
-                    .iter()
-                    .map(ToString::to_string)
-                    .collect::<Vec<String>>()
-                    .join("\n"),
+                div()
+                    .flex()
+                    .size_full()
+                    .child(
+                        div()
+                            .relative()
+                            .flex_1()
+                            .h_full()
+                            .children(
+                                zoomed
+                                    .is_none()
+                                    .then(|| img(image_data).size_full().object_fit(preview_fit.object_fit())),
+                            )
+                            .children(zoomed)
+                            .child(
+                                canvas(
+                                    |_, _, _| {},
+                                    move |bounds, _, window, _| {
+                                        preview_bounds.set(Some(bounds));
+                                        for (position, (width, height), color, stroke_width) in boxes {
+                                            let transform = match zoom {
+                                                Some(region) => FrameTransform::cover_region(bounds, region),
+                                                None => FrameTransform::fit(bounds, width, height, preview_fit),
+                                            };
+                                            window.paint_quad(
+                                                outline(transform.bounding_box(&position), color)
+                                                    .border_widths(px(stroke_width)),
+                                            );
+                                            if show_corners {
+                                                paint_corners(window, transform.corners(&position), color);
+                                            }
+                                        }
+                                    },
+                                )
+                                .absolute()
+                                .size_full(),
+                            )
+                            .children(labels)
+                            .children(self.scan_flash())
+                            .children(spinner)
+                            .children(recording_indicator)
+                            .children(self.presentation_overlay(window))
+                            .children(self.calibration_overlay())
+                            .children(self.recents_overlay()),
+                    )
+                    .children(regenerated),
             )
-            .child(text)
+            .child(div().flex().flex_col().children(results))
+            .children(
+                self.decoder
+                    .sequence_progress()
+                    .into_iter()
+                    .map(|(scanned, total)| format!("Structured append: {}/{} parts", scanned, total)),
+            )
+            .children(self.debug_panel(cx))
+            .children(self.log_panel(cx))
+            .children(self.history_panel())
+            .children(self.tally_list())
+    }
+}
+
+/// Several cameras side by side, each with its own decoder and results. Keys act on the camera last
+/// clicked.
+struct CameraGrid {
+    displays: Vec<Entity<ImageDisplay>>,
+}
+
+impl Render for CameraGrid {
+    fn render(&mut self, _window: &mut Window, _cx: &mut Context<Self>) -> impl IntoElement {
+        div().size_full().flex().gap_1().bg(gpui::rgb(0x404040)).children(
+            self.displays
+                .iter()
+                .map(|display| div().flex_1().h_full().child(display.clone())),
+        )
+    }
+}
+
+/// "qrcam" followed by the cameras being shown, so windows of several instances can be told apart.
+fn window_title(cameras: &[&str]) -> String {
+    match cameras {
+        [] => "qrcam".to_string(),
+        cameras => format!("qrcam — {}", cameras.join(", ")),
+    }
+}
+
+/// The `extract` setting's pattern compiled, `Settings::load` has already dropped it if invalid.
+fn extractor(settings: &Settings) -> Option<Extractor> {
+    settings
+        .extract
+        .as_deref()
+        .and_then(|pattern| Extractor::new(pattern).ok())
+}
+
+/// Wrap an image for display, as the live preview, dropped images and regenerated codes all are, so
+/// they're shown the same way.
+fn render_image(image: RgbaImage) -> Arc<RenderImage> {
+    Arc::new(RenderImage::new(vec![Frame::new(image)]))
+}
+
+/// Step an interval in milliseconds, keeping it within the bounds the log panel allows.
+fn step_interval(interval_ms: u64, delta: i64) -> u64 {
+    interval_ms
+        .saturating_add_signed(delta)
+        .clamp(INTERVAL_MIN_MS, INTERVAL_MAX_MS)
+}
+
+/// Outline a code's quadrilateral and mark its corners, coloured top left red, top right green,
+/// bottom right blue and bottom left yellow so the code's orientation is visible.
+fn paint_corners(window: &mut Window, corners: [Point<gpui::Pixels>; 4], color: gpui::Hsla) {
+    let mut path = PathBuilder::stroke(px(1.));
+    path.move_to(corners[3]);
+    for corner in corners {
+        path.line_to(corner);
+    }
+    match path.build() {
+        Ok(path) => window.paint_path(path, color),
+        Err(err) => log::warn!("failed to build corner outline: {:?}", err),
+    }
+    let colors = [gpui::red(), gpui::green(), gpui::blue(), gpui::yellow()];
+    for (corner, color) in corners.into_iter().zip(colors) {
+        let origin = point(corner.x - px(CORNER_RADIUS), corner.y - px(CORNER_RADIUS));
+        let bounds = Bounds::new(origin, size(px(CORNER_RADIUS * 2.), px(CORNER_RADIUS * 2.)));
+        window.paint_quad(fill(bounds, color).corner_radii(px(CORNER_RADIUS)));
     }
 }
 
-actions!(qr_cam, [Quit]);
+actions!(
+    qr_cam,
+    [
+        Quit,
+        SelectNext,
+        SelectPrevious,
+        CopySelected,
+        OpenSelected,
+        CycleRotation,
+        ToggleThreshold,
+        ToggleFlipVertical,
+        ToggleMirrorPreview,
+        ToggleMirrorDecode,
+        ToggleFocusLock,
+        ToggleNotifications,
+        ToggleFlashOnScan,
+        TogglePreviewFit,
+        TogglePresentation,
+        ToggleSplitView,
+        ToggleRecording,
+        ToggleHighContrast,
+        ToggleAlwaysOnTop,
+        ResetTally,
+        ToggleVerifyRoundTrip,
+        ToggleNewestOnly,
+        ToggleLockOn,
+        ToggleCorners,
+        ToggleDebugPanel,
+        ToggleLogPanel,
+        ToggleHistory,
+        ToggleBoxLabels,
+        ToggleCalibration,
+        TogglePauseDecode,
+        CaptureStill,
+        DecodeCurrent,
+        SaveFrame,
+        DecodeClipboard
+    ]
+);
+
+pub fn main() -> ExitCode {
+    let logs = logs::init();
+    let args = Args::parse();
+    let mut settings = Settings::load();
+    // command line and environment overrides apply to this run only, changes made in the window are saved
+    // on top of what was loaded
+    let file_settings = settings.clone();
+    settings.notify |= args.notify;
+    settings.always_on_top |= args.always_on_top;
+    settings.decode.deskew |= args.deskew;
+    settings.decode.normalize_contrast |= args.normalize_contrast;
+    settings.grayscale_only |= args.grayscale;
+    if args.no_invert {
+        settings.decode.try_invert = false;
+    }
+    if let Some(max_decode_dimension) = args.max_decode_dimension {
+        settings.decode.max_decode_dimension = Some(max_decode_dimension);
+    }
+    if let Some(decode_threads) = args.decode_threads {
+        settings.decode.decode_threads = decode_threads;
+    }
+    if let Some(charset) = args.charset.clone() {
+        settings.decode.charset = Some(charset);
+    }
+    if let Some(backend) = args.decoder {
+        settings.decode.backend = backend;
+    }
+    if let Some(max_results) = args.max_results {
+        settings.decode.max_results = max_results;
+    }
+    if !args.formats.is_empty() {
+        settings.decode.formats = args.formats.clone();
+    }
+    if let Some(mirror) = args.mirror {
+        settings.mirror = Mirror {
+            preview: mirror,
+            decode: mirror,
+        };
+    }
+    if let Some(decode_interval) = args.decode_interval {
+        settings.decode.decode_interval_ms = decode_interval;
+    }
+    settings.capture.discard_late_frames &= !args.keep_late_frames;
+    settings.capture.torch_pulse |= args.torch_pulse;
+    settings.capture.concurrent_queue |= args.concurrent_queue;
+    if let Some(facing) = args.facing {
+        settings.capture.facing = Some(facing);
+    }
+    if let Some((frames, window)) = args.confirm {
+        settings.decode.confirm_frames = frames;
+        settings.decode.confirm_window = window;
+    }
+    if let Some(fps) = args.fps {
+        settings.capture.frame_rate = Some(fps);
+    }
+    if args.list_cameras {
+        return cli::list_cameras();
+    }
+    if args.self_test {
+        return cli::self_test(&settings);
+    }
+    let output = |default| Output {
+        format: args.output_format.unwrap_or(default),
+        normalized_positions: args.normalized_positions,
+    };
+    if args.decode_stdin {
+        return cli::decode_stdin(&settings, output(OutputFormat::Text));
+    }
+    if let Some(dir) = args.decode_dir.as_deref() {
+        return cli::decode_dir(dir, &settings, output(OutputFormat::Json));
+    }
+    if !args.files.is_empty() {
+        return cli::decode_paths(&args.files, &settings, output(OutputFormat::Text));
+    }
+    let mut cameras = Vec::new();
+    for query in &args.cameras {
+        match DeviceInfo::find(query) {
+            Some(device) => cameras.push(device),
+            None => {
+                eprintln!("Error: no camera matching {:?}, see --list-cameras", query);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let video = args.video.clone().map(|path| VideoSource {
+        path,
+        looping: args.loop_video,
+        rate: args.playback_rate,
+    });
+    if let (Some(video), true) = (&video, args.headless) {
+        return cli::decode_video(video, &settings, output(OutputFormat::Text), args.stats);
+    }
+    if args.scan_once {
+        return cli::scan_once(
+            args.timeout.map(Duration::from_secs_f64),
+            &settings,
+            output(OutputFormat::Text),
+            args.stats,
+        );
+    }
 
-pub fn main() {
     Application::new().run(move |cx: &mut App| {
         cx.activate(true);
         cx.on_action(|_: &Quit, cx| cx.quit());
-        cx.bind_keys([KeyBinding::new("ctrl-c", Quit, None)]);
+        cx.bind_keys([
+            KeyBinding::new("ctrl-c", Quit, None),
+            KeyBinding::new("down", SelectNext, None),
+            KeyBinding::new("up", SelectPrevious, None),
+            KeyBinding::new("cmd-c", CopySelected, None),
+            KeyBinding::new("cmd-o", OpenSelected, None),
+            KeyBinding::new("cmd-r", CycleRotation, None),
+            KeyBinding::new("cmd-t", ToggleThreshold, None),
+            KeyBinding::new("cmd-shift-f", ToggleFlipVertical, None),
+            KeyBinding::new("cmd-shift-m", ToggleMirrorPreview, None),
+            KeyBinding::new("cmd-alt-m", ToggleMirrorDecode, None),
+            KeyBinding::new("cmd-l", ToggleFocusLock, None),
+            KeyBinding::new("cmd-shift-o", TogglePreviewFit, None),
+            KeyBinding::new("cmd-p", TogglePresentation, None),
+            KeyBinding::new("cmd-\\", ToggleSplitView, None),
+            KeyBinding::new("cmd-shift-r", ToggleRecording, None),
+            KeyBinding::new("cmd-shift-h", ToggleHighContrast, None),
+            KeyBinding::new("cmd-shift-v", ToggleVerifyRoundTrip, None),
+            KeyBinding::new("cmd-shift-n", ToggleNewestOnly, None),
+            KeyBinding::new("cmd-shift-k", ResetTally, None),
+            KeyBinding::new("cmd-shift-l", ToggleLockOn, None),
+            KeyBinding::new("cmd-shift-c", ToggleCorners, None),
+            KeyBinding::new("cmd-shift-d", ToggleDebugPanel, None),
+            KeyBinding::new("cmd-d", ToggleLogPanel, None),
+            KeyBinding::new("cmd-y", ToggleHistory, None),
+            KeyBinding::new("cmd-shift-b", ToggleBoxLabels, None),
+            KeyBinding::new("cmd-shift-t", ToggleCalibration, None),
+            KeyBinding::new("cmd-shift-p", TogglePauseDecode, None),
+            KeyBinding::new("cmd-shift-s", CaptureStill, None),
+            KeyBinding::new("cmd-e", DecodeCurrent, None),
+            KeyBinding::new("cmd-v", DecodeClipboard, None),
+            KeyBinding::new("cmd-s", SaveFrame, None),
+        ]);
         cx.on_window_closed(|cx| {
             cx.quit();
         })
         .detach();
 
-        let decoder = Decoder::new();
-        let decoder_display = decoder.clone();
+        // one decoder per camera, `None` for the preferred camera
+        let cameras: Vec<Option<DeviceInfo>> = match cameras.is_empty() || video.is_some() {
+            true => vec![None],
+            false => cameras.iter().cloned().map(Some).collect(),
+        };
+        let decoders: Vec<Decoder> = cameras
+            .iter()
+            .map(|_| {
+                let decoder = Decoder::new();
+                decoder.set_rotation(settings.rotation);
+                decoder.set_flip_vertical(settings.flip_vertical);
+                decoder.set_mirror(settings.mirror);
+                decoder.set_grayscale_only(settings.grayscale_only);
+                decoder.set_options(settings.decode.clone());
+                if let Some(command) = args.on_scan.clone() {
+                    decoder.on_result(move |qrcodes| {
+                        for qrcode in qrcodes {
+                            run_on_scan(&command, qrcode);
+                        }
+                    });
+                }
+                decoder
+            })
+            .collect();
+        if let Some(port) = args.http {
+            if let Err(err) = http::serve((args.http_bind, port), decoders[0].clone()) {
+                log::error!("failed to serve http on {}:{}: {}", args.http_bind, port, err);
+            }
+        }
+        let decoders_quit = decoders.clone();
+
+        let history = History::new(extractor(&settings));
+        let history_quit = history.clone();
+        let export_path = args.export.clone();
+        let record_path = args.record.clone();
+        let normalized_positions = args.normalized_positions;
+        let tally = args.tally.is_some().then(Tally::default);
+        let tally_quit = tally.clone();
+        let tally_path = args.tally.clone().flatten();
+        let print_stats = args.stats;
 
         cx.on_app_quit(move |_| {
-            let decoder_quit = decoder.clone();
+            let decoders_quit = decoders_quit.clone();
+            let history_quit = history_quit.clone();
+            let export_path = export_path.clone();
+            let tally_quit = tally_quit.clone();
+            let tally_path = tally_path.clone();
             async move {
-                decoder_quit.shutdown();
+                for decoder in &decoders_quit {
+                    decoder.shutdown();
+                    if print_stats {
+                        cli::print_stats(decoder);
+                    }
+                }
+                if let Some(path) = export_path {
+                    if let Err(err) = history_quit.export(&path, normalized_positions) {
+                        log::error!("failed to export history: {:?}", err);
+                    }
+                }
+                if let (Some(tally), Some(path)) = (tally_quit, tally_path) {
+                    if let Err(err) = tally.export(&path) {
+                        log::error!("failed to export tally: {:?}", err);
+                    }
+                }
             }
         })
         .detach();
 
         cx.set_menus(vec![Menu {
             name: "QR Cam".into(),
-            items: vec![MenuItem::action("Quit", Quit)],
+            items: vec![
+                MenuItem::action("Toggle Notifications", ToggleNotifications),
+                MenuItem::action("Flash on Scan", ToggleFlashOnScan),
+                MenuItem::action("Always on Top", ToggleAlwaysOnTop),
+                MenuItem::separator(),
+                MenuItem::action("Quit", Quit),
+            ],
         }]);
 
         let window_options = WindowOptions {
             titlebar: Some(TitlebarOptions {
+                // cameras found once the window is open are added to the title then
+                title: Some(
+                    window_title(
+                        &cameras
+                            .iter()
+                            .flatten()
+                            .map(|device| device.name.as_str())
+                            .collect::<Vec<_>>(),
+                    )
+                    .into(),
+                ),
                 appears_transparent: true,
                 ..Default::default()
             }),
             window_bounds: Some(WindowBounds::Windowed(Bounds {
-                size: size(px(900.), px(480.)),
+                size: match cameras.len() {
+                    1 => size(px(900.), px(480.)),
+                    count => size(px(640. * count as f32), px(480.)),
+                },
                 origin: Point::new(px(400.), px(100.)),
             })),
             focus: true,
@@ -157,7 +2401,119 @@ pub fn main() {
             ..Default::default()
         };
 
-        cx.open_window(window_options, |_, cx| cx.new(|_| ImageDisplay::new(decoder_display)))
+        let saved_settings = Rc::new(RefCell::new(file_settings));
+        let new_display = |decoder: Decoder, device: Option<DeviceInfo>, cx: &mut Context<ImageDisplay>| {
+            let mut display = ImageDisplay::new(
+                decoder,
+                settings.clone(),
+                saved_settings.clone(),
+                history.clone(),
+                logs.clone(),
+                tally.clone(),
+                record_path.clone(),
+                cx,
+            );
+            display.device = device;
+            display.video = video.clone();
+            display
+        };
+        if let [decoder] = decoders.as_slice() {
+            cx.open_window(window_options, |window, cx| {
+                cx.new(|cx| {
+                    let mut display = new_display(decoder.clone(), cameras[0].clone(), cx);
+                    display.titles_window = true;
+                    window.focus(&display.focus_handle);
+                    display
+                })
+            })
             .unwrap();
+        } else {
+            cx.open_window(window_options, |window, cx| {
+                let displays: Vec<_> = decoders
+                    .iter()
+                    .zip(&cameras)
+                    .map(|(decoder, device)| cx.new(|cx| new_display(decoder.clone(), device.clone(), cx)))
+                    .collect();
+                if let Some(first) = displays.first() {
+                    window.focus(&first.read(cx).focus_handle);
+                }
+                cx.new(|_| CameraGrid { displays })
+            })
+            .unwrap();
+        }
     });
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpui::TestAppContext;
+
+    #[gpui::test]
+    fn close_while_starting(cx: &mut TestAppContext) {
+        let decoder = Decoder::new();
+        let window = cx.add_window(|_, cx| {
+            let mut display = ImageDisplay::new(
+                decoder.clone(),
+                Settings::default(),
+                Rc::new(RefCell::new(Settings::default())),
+                History::new(None),
+                LogBuffer::default(),
+                None,
+                None,
+                cx,
+            );
+            // a file that doesn't exist, so nothing needs a camera and starting ends in an error
+            display.video = Some(VideoSource {
+                path: PathBuf::from("missing.mov"),
+                looping: false,
+                rate: 1.,
+            });
+            // test windows have no native handle to change the level of
+            display.on_top_applied = Some(false);
+            display
+        });
+        window.update(cx, |view, window, cx| view.start(window, cx)).unwrap();
+        // close the window before the task gets to update the view, which used to panic
+        window.update(cx, |_, window, _| window.remove_window()).unwrap();
+        cx.run_until_parked();
+        assert!(window.update(cx, |_, _, _| ()).is_err());
+        decoder.shutdown();
+    }
+
+    #[gpui::test]
+    fn redecode_dropped_records_once(cx: &mut TestAppContext) {
+        let decoder = Decoder::new();
+        let history = History::new(None);
+        let window = cx.add_window(|_, cx| {
+            let mut display = ImageDisplay::new(
+                decoder.clone(),
+                Settings::default(),
+                Rc::new(RefCell::new(Settings::default())),
+                history.clone(),
+                LogBuffer::default(),
+                None,
+                None,
+                cx,
+            );
+            display.on_top_applied = Some(false);
+            display
+        });
+        let code = generate("dropped twice", 200).unwrap();
+        let rgba = DynamicImage::ImageLuma8(code).into_rgba8();
+        window
+            .update(cx, |view, window, cx| {
+                view.show_image("code.png".to_string(), rgba, window, cx)
+            })
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        window.update(cx, |view, _, cx| view.decode_dropped(cx)).unwrap();
+        assert_eq!(history.len(), 1);
+        let qrcodes = window
+            .update(cx, |view, _, _| view.dropped.as_ref().unwrap().qrcodes.len())
+            .unwrap();
+        assert_eq!(qrcodes, 1);
+        decoder.shutdown();
+    }
 }
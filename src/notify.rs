@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use block2::RcBlock;
+use objc2::{
+    class, msg_send, msg_send_id,
+    rc::Id,
+    runtime::{AnyObject, Bool},
+};
+use objc2_foundation::{NSDictionary, NSError, NSString};
+
+use crate::qr::QRCode;
+use crate::sanitize::truncate_for_display;
+
+#[link(name = "AppKit", kind = "framework")]
+extern "C" {
+    static NSAccessibilityAnnouncementRequestedNotification: &'static NSString;
+    static NSAccessibilityAnnouncementKey: &'static NSString;
+
+    fn NSBeep();
+
+    fn NSAccessibilityPostNotificationWithUserInfo(
+        element: &AnyObject,
+        notification: &NSString,
+        user_info: &NSDictionary<NSString, NSString>,
+    );
+}
+
+// nothing is called directly, this loads the classes used through `class!`
+#[link(name = "UserNotifications", kind = "framework")]
+extern "C" {}
+
+/// `UNAuthorizationOptionSound | UNAuthorizationOptionAlert`
+const AUTHORIZATION_OPTIONS: usize = (1 << 1) | (1 << 2);
+
+/// Have VoiceOver (or another assistive app) read out a newly scanned code, does nothing if none is running.
+pub fn announce(qrcode: &QRCode) {
+    let text = NSString::from_str(&truncate_for_display(qrcode.text(), 100));
+    unsafe {
+        let user_info = NSDictionary::from_vec(&[NSAccessibilityAnnouncementKey], vec![text]);
+        let app: Id<AnyObject> = msg_send_id![class!(NSApplication), sharedApplication];
+        NSAccessibilityPostNotificationWithUserInfo(&app, NSAccessibilityAnnouncementRequestedNotification, &user_info);
+    }
+}
+
+/// Play the system alert sound.
+pub fn beep() {
+    unsafe { NSBeep() }
+}
+
+/// Show a desktop notification banner for a newly scanned code.
+///
+/// The first call asks for permission to show notifications. macOS only shows them for apps run from a
+/// bundle, so run as a bare binary (e.g. with `cargo run`) this logs a warning once and shows nothing.
+pub fn notify(qrcode: &QRCode) {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    if !notifications_available() {
+        return;
+    }
+    let title = NSString::from_str(&format!("Scanned {}", qrcode.format()));
+    let text = NSString::from_str(&truncate_for_display(qrcode.text(), 100));
+    let identifier = NSString::from_str(&format!("qrcam-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed)));
+    unsafe {
+        let content: Id<AnyObject> = msg_send_id![class!(UNMutableNotificationContent), new];
+        let _: () = msg_send![&*content, setTitle: &*title];
+        let _: () = msg_send![&*content, setBody: &*text];
+        // no trigger shows it straight away
+        let trigger: Option<&AnyObject> = None;
+        let request: Id<AnyObject> = msg_send_id![
+            class!(UNNotificationRequest),
+            requestWithIdentifier: &*identifier,
+            content: &*content,
+            trigger: trigger
+        ];
+        let completion_handler = RcBlock::new(|error: *mut NSError| {
+            if let Some(error) = error.as_ref() {
+                log::warn!("failed to show notification: {}", error.localizedDescription());
+            }
+        });
+        let center: Id<AnyObject> = msg_send_id![class!(UNUserNotificationCenter), currentNotificationCenter];
+        let _: () = msg_send![&*center, addNotificationRequest: &*request, withCompletionHandler: &*completion_handler];
+    }
+}
+
+/// Whether notifications can be shown, asking for permission the first time it's called.
+fn notifications_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| unsafe {
+        // UNUserNotificationCenter throws rather than returning an error outside a bundle
+        let bundle: Id<AnyObject> = msg_send_id![class!(NSBundle), mainBundle];
+        let bundle_id: Option<Id<NSString>> = msg_send_id![&*bundle, bundleIdentifier];
+        if bundle_id.is_none() {
+            log::warn!("notifications are only shown when qrcam is run from an app bundle, not showing any");
+            return false;
+        }
+        let completion_handler = RcBlock::new(|granted: Bool, error: *mut NSError| {
+            if let Some(error) = error.as_ref() {
+                log::warn!(
+                    "failed to request notification permission: {}",
+                    error.localizedDescription()
+                );
+            } else if !granted.as_bool() {
+                log::warn!("notifications are turned off for qrcam in System Settings, not showing any");
+            }
+        });
+        let center: Id<AnyObject> = msg_send_id![class!(UNUserNotificationCenter), currentNotificationCenter];
+        let _: () = msg_send![
+            &*center,
+            requestAuthorizationWithOptions: AUTHORIZATION_OPTIONS,
+            completionHandler: &*completion_handler
+        ];
+        true
+    })
+}
@@ -0,0 +1,88 @@
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::history::csv_field;
+use crate::qr::QRCode;
+use crate::sanitize::escape_control;
+
+/// How the command line decode modes print the codes they find.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Each code's text on its own line, after the file name and a tab when decoding files.
+    Text,
+    /// A header row then a row per code with the file (empty when not decoding files), text, format
+    /// and corners.
+    Csv,
+    /// A JSON object per file, or per scan, with the text, format and position of each code.
+    Json,
+}
+
+const CSV_HEADER: &str = "file,text,format,top_left_x,top_left_y,top_right_x,top_right_y,\
+bottom_right_x,bottom_right_y,bottom_left_x,bottom_left_y";
+
+/// Formats decoded codes the same way for every command line decode mode.
+#[derive(Clone, Copy, Debug)]
+pub struct Output {
+    pub format: OutputFormat,
+    /// Include positions as fractions of the frame size in JSON output.
+    pub normalized_positions: bool,
+}
+
+impl Output {
+    /// Print anything that comes before the first codes, i.e. the CSV header.
+    pub fn begin(&self) {
+        if self.format == OutputFormat::Csv {
+            println!("{}", CSV_HEADER);
+        }
+    }
+
+    /// Print the codes decoded from `file`, or from the camera or stdin when `None`.
+    pub fn print(&self, file: Option<&Path>, qrcodes: &[QRCode]) {
+        for line in self.lines(file, qrcodes) {
+            println!("{}", line);
+        }
+    }
+
+    /// The lines `print` prints, text is escaped so it can't take over the terminal.
+    pub fn lines(&self, file: Option<&Path>, qrcodes: &[QRCode]) -> Vec<String> {
+        let file_name = file.map(|file| escape_control(&file.to_string_lossy()));
+        match self.format {
+            OutputFormat::Text => qrcodes
+                .iter()
+                .map(|qrcode| match &file_name {
+                    Some(file_name) => format!("{}\t{}", file_name, escape_control(qrcode.text())),
+                    None => escape_control(qrcode.text()),
+                })
+                .collect(),
+            OutputFormat::Csv => qrcodes
+                .iter()
+                .map(|qrcode| {
+                    let mut fields = vec![
+                        file_name.as_deref().map(csv_field).unwrap_or_default(),
+                        csv_field(&escape_control(qrcode.text())),
+                        csv_field(&qrcode.format().to_string()),
+                    ];
+                    fields.extend(qrcode.corners().iter().flatten().map(ToString::to_string));
+                    fields.join(",")
+                })
+                .collect(),
+            OutputFormat::Json => {
+                // `codes` is kept as a list of text, as --decode-dir has always printed it
+                let mut object = json!({
+                    "codes": qrcodes.iter().map(QRCode::text).collect::<Vec<_>>(),
+                    "formats": qrcodes.iter().map(|qrcode| qrcode.format().to_string()).collect::<Vec<_>>(),
+                    "positions": qrcodes.iter().map(QRCode::corners).collect::<Vec<_>>(),
+                });
+                if let Some(file) = file {
+                    object["file"] = json!(file);
+                }
+                if self.normalized_positions {
+                    object["normalized_positions"] =
+                        json!(qrcodes.iter().map(QRCode::normalized_corners).collect::<Vec<_>>());
+                }
+                vec![object.to_string()]
+            }
+        }
+    }
+}
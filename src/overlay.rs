@@ -0,0 +1,306 @@
+use std::fmt;
+
+use gpui::{point, px, size, Bounds, Hsla, ObjectFit, Pixels, Point, Rgba};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use zxingcpp::Position;
+
+/// How bounding boxes are drawn over the preview.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OverlayStyle {
+    /// Box colour as a hex string, e.g. `#00ff00`.
+    pub color: String,
+    pub stroke_width: f32,
+    /// Ignore `color` and pick black or white, whichever contrasts with the frame under each box.
+    pub auto_contrast: bool,
+    /// Show each code's text at its box, instead of listing codes below the preview.
+    pub label_boxes: bool,
+}
+
+impl Default for OverlayStyle {
+    fn default() -> Self {
+        Self {
+            color: "#00ff00".to_string(),
+            stroke_width: 2.,
+            auto_contrast: false,
+            label_boxes: false,
+        }
+    }
+}
+
+impl OverlayStyle {
+    pub fn color(&self) -> Hsla {
+        Rgba::try_from(self.color.as_str())
+            .map(Into::into)
+            .unwrap_or_else(|_| gpui::green())
+    }
+
+    /// The colour for a box over a region with the given average luma (0-255).
+    pub fn color_for(&self, luma: Option<f32>) -> Hsla {
+        match luma {
+            Some(luma) if self.auto_contrast => {
+                if luma > 128. {
+                    gpui::black()
+                } else {
+                    gpui::white()
+                }
+            }
+            _ => self.color(),
+        }
+    }
+}
+
+/// The code size calibration mode guides the camera towards when setting up a scanning station.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    /// Module size in frame pixels to aim for, codes decode reliably from around 3 pixels a module.
+    pub module_px: f64,
+    /// Modules across the codes to be scanned, e.g. 25 for a version 2 QR code, used to estimate the
+    /// module size from a code's size in the frame.
+    pub modules: u32,
+    /// How far the module size can be from `module_px`, as a fraction of it, and still be right.
+    pub tolerance: f64,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            module_px: 5.,
+            modules: 25,
+            tolerance: 0.25,
+        }
+    }
+}
+
+impl Calibration {
+    /// Estimated module size in pixels of a code `side` pixels across.
+    pub fn module_size(&self, side: f64) -> f64 {
+        side / self.modules.max(1) as f64
+    }
+
+    /// Which way to move the camera for codes of `module_size` pixels a module.
+    pub fn guidance(&self, module_size: f64) -> Guidance {
+        if module_size < self.module_px * (1. - self.tolerance) {
+            Guidance::MoveCloser
+        } else if module_size > self.module_px * (1. + self.tolerance) {
+            Guidance::MoveFurther
+        } else {
+            Guidance::Good
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Guidance {
+    MoveCloser,
+    MoveFurther,
+    Good,
+}
+
+impl fmt::Display for Guidance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::MoveCloser => "move closer",
+            Self::MoveFurther => "move further away",
+            Self::Good => "good distance",
+        })
+    }
+}
+
+/// How the preview is scaled into the window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewFit {
+    /// Fill the preview, cropping the edges of the frame where the aspect ratios differ, so codes near
+    /// the edges can be decoded without being visible.
+    #[default]
+    Cover,
+    /// Show the whole frame, letterboxed where the aspect ratios differ.
+    Contain,
+}
+
+impl PreviewFit {
+    pub fn toggled(self) -> Self {
+        match self {
+            Self::Cover => Self::Contain,
+            Self::Contain => Self::Cover,
+        }
+    }
+
+    pub fn object_fit(self) -> ObjectFit {
+        match self {
+            Self::Cover => ObjectFit::Cover,
+            Self::Contain => ObjectFit::Contain,
+        }
+    }
+}
+
+/// Average luma (0-255) of the frame within a code's bounding box, sampling every few pixels.
+pub fn average_luma(img: &RgbaImage, position: &Position) -> Option<f32> {
+    let (min, max) = corner_bounds(position);
+    let x_range = min.0.max(0) as u32..(max.0.max(0) as u32).min(img.width());
+    let y_range = min.1.max(0) as u32..(max.1.max(0) as u32).min(img.height());
+    let (mut total, mut count) = (0., 0);
+    for y in y_range.step_by(4) {
+        for x in x_range.clone().step_by(4) {
+            let [r, g, b, _] = img.get_pixel(x, y).0;
+            total += 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+            count += 1;
+        }
+    }
+    (count > 0).then(|| total / count as f32)
+}
+
+/// Top left and bottom right of the axis-aligned box enclosing a position's corners.
+fn corner_bounds(position: &Position) -> ((i32, i32), (i32, i32)) {
+    let corners = [
+        position.top_left,
+        position.top_right,
+        position.bottom_right,
+        position.bottom_left,
+    ];
+    let min_x = corners.iter().map(|c| c.x).min().unwrap_or_default();
+    let min_y = corners.iter().map(|c| c.y).min().unwrap_or_default();
+    let max_x = corners.iter().map(|c| c.x).max().unwrap_or_default();
+    let max_y = corners.iter().map(|c| c.y).max().unwrap_or_default();
+    ((min_x, min_y), (max_x, max_y))
+}
+
+/// A rectangle of a frame in frame pixels, e.g. the part of the preview zoomed in on in lock on mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameRegion {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl FrameRegion {
+    pub fn full((width, height): (u32, u32)) -> Self {
+        Self {
+            x: 0.,
+            y: 0.,
+            width: width as f32,
+            height: height as f32,
+        }
+    }
+
+    /// A square around a code with half the code's size again as margin on each side, kept within the frame.
+    pub fn around(position: &Position, frame_size: (u32, u32)) -> Self {
+        let margin = 0.5;
+        let full = Self::full(frame_size);
+        let ((min_x, min_y), (max_x, max_y)) = corner_bounds(position);
+        let side = ((max_x - min_x).max(max_y - min_y) as f32 * (1. + 2. * margin))
+            .min(full.width)
+            .min(full.height);
+        let center_x = (min_x + max_x) as f32 / 2.;
+        let center_y = (min_y + max_y) as f32 / 2.;
+        Self {
+            x: (center_x - side / 2.).clamp(0., full.width - side),
+            y: (center_y - side / 2.).clamp(0., full.height - side),
+            width: side,
+            height: side,
+        }
+    }
+
+    /// Move `amount` (0.0 to 1.0) of the way towards `target`, for smoothing frame-to-frame jitter.
+    pub fn approach(self, target: Self, amount: f32) -> Self {
+        let step = |from: f32, to: f32| from + (to - from) * amount;
+        Self {
+            x: step(self.x, target.x),
+            y: step(self.y, target.y),
+            width: step(self.width, target.width),
+            height: step(self.height, target.height),
+        }
+    }
+
+    /// Whether every edge is within a pixel of `other`'s.
+    pub fn close_to(&self, other: &Self) -> bool {
+        (self.x - other.x).abs() < 1.
+            && (self.y - other.y).abs() < 1.
+            && (self.width - other.width).abs() < 1.
+            && (self.height - other.height).abs() < 1.
+    }
+}
+
+/// Maps frame pixel coordinates onto the bounds of the preview.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameTransform {
+    scale: f32,
+    offset: Point<Pixels>,
+}
+
+impl FrameTransform {
+    /// For a frame drawn with `fit`, centred in the bounds.
+    pub fn fit(bounds: Bounds<Pixels>, frame_width: u32, frame_height: u32, fit: PreviewFit) -> Self {
+        match fit {
+            PreviewFit::Cover => Self::cover(bounds, frame_width, frame_height),
+            PreviewFit::Contain => Self::contain(bounds, frame_width, frame_height),
+        }
+    }
+
+    pub fn cover(bounds: Bounds<Pixels>, frame_width: u32, frame_height: u32) -> Self {
+        Self::cover_region(bounds, FrameRegion::full((frame_width, frame_height)))
+    }
+
+    /// Scaled so the whole frame fits the bounds, offset by the letterboxing on either side.
+    pub fn contain(bounds: Bounds<Pixels>, frame_width: u32, frame_height: u32) -> Self {
+        let region = FrameRegion::full((frame_width, frame_height));
+        let scale = (bounds.size.width.0 / region.width).min(bounds.size.height.0 / region.height);
+        Self::centered(bounds, region, scale)
+    }
+
+    /// Like `cover`, but scaled so that just `region` of the frame covers the bounds.
+    pub fn cover_region(bounds: Bounds<Pixels>, region: FrameRegion) -> Self {
+        let scale = (bounds.size.width.0 / region.width).max(bounds.size.height.0 / region.height);
+        Self::centered(bounds, region, scale)
+    }
+
+    /// `region` of the frame at `scale`, centred in the bounds.
+    fn centered(bounds: Bounds<Pixels>, region: FrameRegion, scale: f32) -> Self {
+        let offset = point(
+            bounds.origin.x + px((bounds.size.width.0 - region.width * scale) / 2. - region.x * scale),
+            bounds.origin.y + px((bounds.size.height.0 - region.height * scale) / 2. - region.y * scale),
+        );
+        Self { scale, offset }
+    }
+
+    /// Where the whole frame is drawn, which may extend beyond the preview bounds.
+    pub fn frame_bounds(&self, frame_width: u32, frame_height: u32) -> Bounds<Pixels> {
+        Bounds::new(
+            self.offset,
+            size(
+                px(frame_width as f32 * self.scale),
+                px(frame_height as f32 * self.scale),
+            ),
+        )
+    }
+
+    pub fn point(&self, x: i32, y: i32) -> Point<Pixels> {
+        point(
+            self.offset.x + px(x as f32 * self.scale),
+            self.offset.y + px(y as f32 * self.scale),
+        )
+    }
+
+    /// The corners of a decoded position (top left, top right, bottom right, bottom left) in the preview.
+    pub fn corners(&self, position: &Position) -> [Point<Pixels>; 4] {
+        [
+            position.top_left,
+            position.top_right,
+            position.bottom_right,
+            position.bottom_left,
+        ]
+        .map(|corner| self.point(corner.x, corner.y))
+    }
+
+    /// Axis-aligned box enclosing all four corners of a decoded position.
+    pub fn bounding_box(&self, position: &Position) -> Bounds<Pixels> {
+        let ((min_x, min_y), (max_x, max_y)) = corner_bounds(position);
+        let origin = self.point(min_x, min_y);
+        let bottom_right = self.point(max_x, max_y);
+        Bounds::new(origin, size(bottom_right.x - origin.x, bottom_right.y - origin.y))
+    }
+}
@@ -0,0 +1,325 @@
+/// How a network from a `WIFI:` code is secured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WifiSecurity {
+    Open,
+    Wep,
+    /// Any WPA variant, including WPA3 (`SAE`) and enterprise (`WPA2-EAP`).
+    Wpa,
+}
+
+impl WifiSecurity {
+    /// Parse a `T:` value, `None` if it isn't one phones generate.
+    fn from_label(label: &str) -> Option<Self> {
+        let label = label.to_ascii_uppercase();
+        match label.as_str() {
+            "" | "NOPASS" => Some(Self::Open),
+            "WEP" => Some(Self::Wep),
+            _ if label.starts_with("WPA") || label == "SAE" => Some(Self::Wpa),
+            _ => None,
+        }
+    }
+}
+
+/// A network from a `WIFI:` code, as phones share them and router labels print them, e.g.
+/// `WIFI:T:WPA;S:my network;P:secret;;`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WifiNetwork {
+    pub ssid: String,
+    /// `None` for an open network, including one with an empty `P:`.
+    pub password: Option<String>,
+    /// Without a `T:`, WPA if there's a password and open if not.
+    pub security: WifiSecurity,
+    /// The network doesn't broadcast its SSID.
+    pub hidden: bool,
+}
+
+/// A contact from a `MECARD:` code, e.g. `MECARD:N:Doe,John;TEL:+15551234;;`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Contact {
+    /// First name first, MECARD has it as `last,first`.
+    pub name: String,
+    pub phones: Vec<String>,
+    pub emails: Vec<String>,
+    pub address: Option<String>,
+    pub url: Option<String>,
+    pub note: Option<String>,
+}
+
+/// Parse a `WIFI:` code, `None` if `text` isn't one or is malformed, e.g. has no SSID.
+///
+/// `\`, `;`, `,`, `:` and `"` can be escaped with a backslash, and values may be wrapped in double quotes
+/// (generators do this for SSIDs that would otherwise read as hex).
+pub fn parse_wifi(text: &str) -> Option<WifiNetwork> {
+    let (mut ssid, mut password, mut security, mut hidden) = (None, None, None, false);
+    for (key, value) in fields(text, "WIFI:")? {
+        let value = unescape(unquote(value))?;
+        match key.to_ascii_uppercase().as_str() {
+            "S" => ssid = Some(value),
+            "P" => password = Some(value).filter(|password| !password.is_empty()),
+            "T" => security = Some(WifiSecurity::from_label(&value)?),
+            "H" => hidden = value.eq_ignore_ascii_case("true"),
+            _ => {}
+        }
+    }
+    let ssid = ssid.filter(|ssid| !ssid.is_empty())?;
+    let security = security.unwrap_or(match password {
+        Some(_) => WifiSecurity::Wpa,
+        None => WifiSecurity::Open,
+    });
+    Some(WifiNetwork {
+        ssid,
+        password,
+        security,
+        hidden,
+    })
+}
+
+/// Parse a `MECARD:` code, `None` if `text` isn't one or is malformed, e.g. has no name. Escapes are the
+/// same as in `parse_wifi`.
+pub fn parse_mecard(text: &str) -> Option<Contact> {
+    let mut contact = Contact::default();
+    for (key, value) in fields(text, "MECARD:")? {
+        match key.to_ascii_uppercase().as_str() {
+            "N" => contact.name = mecard_name(value)?,
+            "TEL" => contact.phones.push(unescape(value)?),
+            "EMAIL" => contact.emails.push(unescape(value)?),
+            "ADR" => contact.address = Some(unescape(value)?),
+            "URL" => contact.url = Some(unescape(value)?),
+            "NOTE" => contact.note = Some(unescape(value)?),
+            _ => {}
+        }
+    }
+    (!contact.name.is_empty()).then_some(contact)
+}
+
+/// The `KEY:value` fields after `prefix`, with their values still escaped. `None` if `text` doesn't
+/// start with `prefix` (ignoring case) or a field has no key.
+fn fields<'a>(text: &'a str, prefix: &str) -> Option<Vec<(&'a str, &'a str)>> {
+    let text = text.trim();
+    if !text.get(..prefix.len())?.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    // the closing `;;` leaves an empty field, which is skipped like any other
+    split_unescaped(&text[prefix.len()..], ';')
+        .into_iter()
+        .filter(|field| !field.is_empty())
+        .map(|field| field.split_once(':'))
+        .collect()
+}
+
+/// MECARD's `last,first` as `first last`, leaving out empty parts.
+fn mecard_name(value: &str) -> Option<String> {
+    let parts = split_unescaped(value, ',')
+        .into_iter()
+        .map(unescape)
+        .collect::<Option<Vec<_>>>()?;
+    Some(
+        parts
+            .iter()
+            .rev()
+            .map(|part| part.trim())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Split `text` at each `separator` not escaped with a backslash, leaving the escapes in place.
+fn split_unescaped(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == separator => {
+                parts.push(&text[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Remove backslash escapes, `None` if `text` ends part way through one.
+fn unescape(text: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(if c == '\\' { chars.next()? } else { c });
+    }
+    Some(unescaped)
+}
+
+/// `value` without the double quotes around it, if it has them and the closing one isn't escaped.
+fn unquote(value: &str) -> &str {
+    match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+        Some(inner) if inner.chars().rev().take_while(|&c| c == '\\').count() % 2 == 0 => inner,
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wifi(ssid: &str, password: Option<&str>, security: WifiSecurity, hidden: bool) -> Option<WifiNetwork> {
+        Some(WifiNetwork {
+            ssid: ssid.to_string(),
+            password: password.map(str::to_string),
+            security,
+            hidden,
+        })
+    }
+
+    #[test]
+    fn wifi_payloads() {
+        use WifiSecurity::*;
+        let cases = [
+            (
+                "WIFI:T:WPA;S:mynetwork;P:mypass;;",
+                wifi("mynetwork", Some("mypass"), Wpa, false),
+            ),
+            // Android's share sheet puts the SSID first
+            (
+                "WIFI:S:Home 5G;T:WPA;P:correct horse;H:false;;",
+                wifi("Home 5G", Some("correct horse"), Wpa, false),
+            ),
+            (
+                r"WIFI:S:Coffee\;Shop;T:WPA;P:pa\:ss\,word\\;;",
+                wifi("Coffee;Shop", Some(r"pa:ss,word\"), Wpa, false),
+            ),
+            (r"WIFI:S:a\:b\,c\\d\;;P:;;", wifi(r"a:b,c\d;", None, Open, false)),
+            (
+                r#"WIFI:T:WPA;S:say \"hi\";P:"q\"uote";;"#,
+                wifi(r#"say "hi""#, Some(r#"q"uote"#), Wpa, false),
+            ),
+            (
+                "WIFI:T:WPA;S:\"414243\";P:\"hunter2\";;",
+                wifi("414243", Some("hunter2"), Wpa, false),
+            ),
+            ("WIFI:T:nopass;S:Guest;P:;;", wifi("Guest", None, Open, false)),
+            ("WIFI:S:Open Cafe;;", wifi("Open Cafe", None, Open, false)),
+            ("WIFI:S:Implied;P:secret;;", wifi("Implied", Some("secret"), Wpa, false)),
+            (
+                "WIFI:T:WEP;S:old router;P:12345;H:true;;",
+                wifi("old router", Some("12345"), Wep, true),
+            ),
+            (
+                "WIFI:T:SAE;S:wpa3;P:p@$$!#%&*()[]{}<>;H:TRUE;;",
+                wifi("wpa3", Some("p@$$!#%&*()[]{}<>"), Wpa, true),
+            ),
+            (
+                "WIFI:T:WPA2-EAP;S:corp;P:pw;E:PEAP;I:me;;",
+                wifi("corp", Some("pw"), Wpa, false),
+            ),
+            ("wifi:t:wpa;s:lower;p:case;;", wifi("lower", Some("case"), Wpa, false)),
+            (
+                "WIFI:S:no terminator;T:WPA;P:x",
+                wifi("no terminator", Some("x"), Wpa, false),
+            ),
+            (
+                "WIFI:S:Ünïcödé 📶;T:WPA;P:пароль;;\n",
+                wifi("Ünïcödé 📶", Some("пароль"), Wpa, false),
+            ),
+        ];
+        for (payload, expected) in cases {
+            assert_eq!(parse_wifi(payload), expected, "{:?}", payload);
+        }
+    }
+
+    #[test]
+    fn wifi_malformed() {
+        let cases = [
+            "",
+            "WIFI",
+            "WIFI:",
+            "WIFI:;;",
+            "WIFI:S:;;",
+            "WIFI:T:WPA;P:secret;;",
+            r"WIFI:S:dangling\",
+            r"WIFI:S:net;P:dangling\",
+            "WIFI:S:net;garbage;;",
+            "WIFI:T:ROT13;S:net;;",
+            "WIFIS:net;;",
+            "MECARD:N:Doe,John;;",
+            "https://example.com/WIFI:S:net;;",
+            "WIFI📶",
+        ];
+        for payload in cases {
+            assert_eq!(parse_wifi(payload), None, "{:?}", payload);
+        }
+    }
+
+    #[test]
+    fn mecard_payloads() {
+        let cases = [
+            (
+                "MECARD:N:Doe,John;TEL:+15551234;EMAIL:john@example.com;;",
+                Contact {
+                    name: "John Doe".to_string(),
+                    phones: vec!["+15551234".to_string()],
+                    emails: vec!["john@example.com".to_string()],
+                    ..Contact::default()
+                },
+            ),
+            (
+                r"MECARD:N:Smith\, Jr.,Bob;TEL:111;TEL:222;;",
+                Contact {
+                    name: "Bob Smith, Jr.".to_string(),
+                    phones: vec!["111".to_string(), "222".to_string()],
+                    ..Contact::default()
+                },
+            ),
+            (
+                r"MECARD:N:O\\Brien,Pat;ADR:1 Main St\;Apt 2,Springfield;NOTE:call\; don't text;;",
+                Contact {
+                    name: r"Pat O\Brien".to_string(),
+                    address: Some("1 Main St;Apt 2,Springfield".to_string()),
+                    note: Some("call; don't text".to_string()),
+                    ..Contact::default()
+                },
+            ),
+            (
+                "MECARD:N:Lee;URL:https://example.com/a?b=c;BDAY:19700101;;",
+                Contact {
+                    name: "Lee".to_string(),
+                    url: Some("https://example.com/a?b=c".to_string()),
+                    ..Contact::default()
+                },
+            ),
+            (
+                "mecard:n:Ünïcödé, 名前",
+                Contact {
+                    name: "名前 Ünïcödé".to_string(),
+                    ..Contact::default()
+                },
+            ),
+        ];
+        for (payload, expected) in cases {
+            assert_eq!(parse_mecard(payload), Some(expected), "{:?}", payload);
+        }
+    }
+
+    #[test]
+    fn mecard_malformed() {
+        let cases = [
+            "",
+            "MECARD:",
+            "MECARD:;;",
+            "MECARD:TEL:123;;",
+            "MECARD:N:,;;",
+            r"MECARD:N:dangling\",
+            r"MECARD:N:Doe;TEL:dangling\",
+            "MECARD:N:Doe;TEL;;",
+            "BEGIN:VCARD\nFN:John Doe\nEND:VCARD",
+            "WIFI:S:net;;",
+        ];
+        for payload in cases {
+            assert_eq!(parse_mecard(payload), None, "{:?}", payload);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use zxingcpp::BarcodeFormat;
+
+/// Where to look up product names for EAN/UPC codes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProductLookup {
+    pub enabled: bool,
+    /// URL to fetch, with `{code}` replaced by the decoded number.
+    pub url: String,
+    /// JSON pointer to the product name within the response.
+    pub name_pointer: String,
+}
+
+impl Default for ProductLookup {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "https://world.openfoodfacts.org/api/v2/product/{code}.json".to_string(),
+            name_pointer: "/product/product_name".to_string(),
+        }
+    }
+}
+
+pub fn is_product_format(format: BarcodeFormat) -> bool {
+    [
+        BarcodeFormat::EAN13,
+        BarcodeFormat::EAN8,
+        BarcodeFormat::UPCA,
+        BarcodeFormat::UPCE,
+    ]
+    .contains(&format)
+}
+
+/// Fetch the name of a product, `Ok(None)` means the endpoint doesn't know the code.
+///
+/// This blocks on the network so should be run on a background thread.
+pub fn lookup(config: &ProductLookup, code: &str) -> Result<Option<String>> {
+    // product codes are all digits, anything else would end up in the URL
+    anyhow::ensure!(
+        code.chars().all(|c| c.is_ascii_digit()),
+        "invalid product code {:?}",
+        code
+    );
+    let url = config.url.replace("{code}", code);
+    let response = match ureq::get(&url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(404, _)) => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("failed to fetch {}", url)),
+    };
+    let body: serde_json::Value = response.into_json().context("invalid JSON response")?;
+    Ok(body
+        .pointer(&config.name_pointer)
+        .and_then(serde_json::Value::as_str)
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string))
+}
@@ -1,17 +1,385 @@
+use encoding_rs::Encoding;
+use image::imageops::{resize, FilterType};
 use image::GrayImage;
+use imageproc::contrast::stretch_contrast;
+use imageproc::stats::percentile;
+use serde::{Deserialize, Serialize};
 use std::{
     fmt,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, Mutex,
     },
+    time::{Duration, Instant},
 };
-use zxingcpp::{Barcode, BarcodeFormat, Position};
+use zxingcpp::{Barcode, BarcodeFormat, BarcodeFormats, BarcodeReader, PointI, Position};
 
-#[derive(Debug)]
+use crate::decode::lock;
+use crate::dedup::{Dedup, GONE_AFTER_MS};
+use crate::deskew::decode_normalized;
+use crate::sanitize::{truncate_for_display, MAX_DISPLAY_CHARS};
+use crate::sequence::Sequences;
+use crate::stats::DecodeStats;
+use crate::tile::decode_tiled;
+
+/// Barcode formats that can be enabled for decoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeFormat {
+    QrCode,
+    MicroQrCode,
+    DataMatrix,
+    Aztec,
+    Pdf417,
+    Ean8,
+    Ean13,
+    UpcA,
+    UpcE,
+    Code39,
+    Code128,
+}
+
+impl CodeFormat {
+    pub const ALL: [Self; 11] = [
+        Self::QrCode,
+        Self::MicroQrCode,
+        Self::DataMatrix,
+        Self::Aztec,
+        Self::Pdf417,
+        Self::Ean8,
+        Self::Ean13,
+        Self::UpcA,
+        Self::UpcE,
+        Self::Code39,
+        Self::Code128,
+    ];
+
+    pub fn barcode_format(self) -> BarcodeFormat {
+        match self {
+            Self::QrCode => BarcodeFormat::QRCode,
+            Self::MicroQrCode => BarcodeFormat::MicroQRCode,
+            Self::DataMatrix => BarcodeFormat::DataMatrix,
+            Self::Aztec => BarcodeFormat::Aztec,
+            Self::Pdf417 => BarcodeFormat::PDF417,
+            Self::Ean8 => BarcodeFormat::EAN8,
+            Self::Ean13 => BarcodeFormat::EAN13,
+            Self::UpcA => BarcodeFormat::UPCA,
+            Self::UpcE => BarcodeFormat::UPCE,
+            Self::Code39 => BarcodeFormat::Code39,
+            Self::Code128 => BarcodeFormat::Code128,
+        }
+    }
+
+    /// The `AVMetadataObjectType` the camera's own detector reports this format as, `None` for UPC-A
+    /// which it reports as EAN-13 with a leading zero.
+    pub fn metadata_type(self) -> Option<&'static str> {
+        match self {
+            Self::QrCode => Some("org.iso.QRCode"),
+            Self::MicroQrCode => Some("org.iso.MicroQR"),
+            Self::DataMatrix => Some("org.iso.DataMatrix"),
+            Self::Aztec => Some("org.iso.Aztec"),
+            Self::Pdf417 => Some("org.iso.PDF417"),
+            Self::Ean8 => Some("org.gs1.EAN-8"),
+            Self::Ean13 => Some("org.gs1.EAN-13"),
+            Self::UpcA => None,
+            Self::UpcE => Some("org.gs1.UPC-E"),
+            Self::Code39 => Some("org.iso.Code39"),
+            Self::Code128 => Some("org.iso.Code128"),
+        }
+    }
+
+    pub fn from_metadata_type(metadata_type: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.metadata_type() == Some(metadata_type))
+    }
+}
+
+/// What finds codes in camera frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// zxing-cpp on the decode thread, on the CPU.
+    #[default]
+    Zxing,
+    /// The camera's own detector via `AVCaptureMetadataOutput` (macOS 13+), which may use less power.
+    /// Falls back to zxing-cpp where it's unavailable or can't find every enabled format. Doesn't apply to images
+    /// decoded from files.
+    Native,
+}
+
+/// Options controlling how frames are decoded, the decode thread picks up changes on the next frame.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecodeOptions {
+    /// When a frame has no codes, retry with contrast normalization and mild keystone corrections
+    /// to catch codes photographed at an angle. Off by default as it can cost several extra decodes.
+    pub deskew: bool,
+    /// Stretch each frame's contrast before decoding, for codes on glossy or low-contrast surfaces. Only
+    /// the image decoded is changed, not the preview. Off by default as it costs CPU on every frame and
+    /// can occasionally hurt clean codes.
+    pub normalize_contrast: bool,
+    pub formats: Vec<CodeFormat>,
+    /// Most codes reported per frame, zxing-cpp stops looking once it has found this many, so
+    /// lowering it also speeds up decoding busy frames.
+    pub max_results: u32,
+    /// Also look for light-on-dark codes (e.g. on dark-mode or e-ink screens). This retries every
+    /// frame with no codes inverted, roughly doubling the decode cost of empty frames.
+    pub try_invert: bool,
+    /// Spend more time looking for codes, e.g. trying rotations. zxing-cpp's default, disabling it
+    /// speeds up decoding at the cost of missing harder codes.
+    pub try_harder: bool,
+    /// Downscale frames so their longest side is at most this many pixels before decoding, much
+    /// faster on 4K frames at the cost of missing the smallest codes. No limit by default.
+    pub max_decode_dimension: Option<u32>,
+    /// Milliseconds after a code was last decoded before it's considered to have left the frame, so
+    /// that it's reported as new (beeping, tallying and adding to the history) when it's next decoded.
+    /// Too short and a code missed for a few frames is reported again while still in view.
+    pub gone_after_ms: u64,
+    /// Shortest time in milliseconds before a code can be reported as new again, even if it left
+    /// the frame in between, so notifications and other actions on new codes don't repeat.
+    /// Copying or opening a code by hand isn't affected. A code that returns after `gone_after_ms`
+    /// is only reported once this has also passed since it last was.
+    pub cooldown_ms: u64,
+    /// Only report a code as newly scanned once it's been decoded in at least this many of the last
+    /// `confirm_window` frames, to suppress noise misread as a code for a single frame at the cost of a
+    /// little latency. 1, the default, reports a code the first frame it's decoded in.
+    pub confirm_frames: u32,
+    /// How many recent frames `confirm_frames` counts over, at least `confirm_frames`.
+    pub confirm_window: u32,
+    /// Decode text in this character set, as a label like `shift_jis` or `iso-8859-1`, for codes
+    /// whose encoding zxing-cpp gets wrong. By default the code's ECI or zxing-cpp's guess is used.
+    pub charset: Option<String>,
+    pub backend: Backend,
+    /// Split each frame into this many overlapping bands decoded concurrently, which can be faster
+    /// on frames with many codes. 1, the default, decodes the whole frame on the decode thread.
+    pub decode_threads: u32,
+    /// Milliseconds the decode thread sleeps before picking up the latest frame, lower for less latency
+    /// at the cost of more CPU.
+    pub decode_interval_ms: u64,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            deskew: false,
+            normalize_contrast: false,
+            formats: vec![CodeFormat::QrCode],
+            max_results: 8,
+            try_invert: true,
+            try_harder: true,
+            max_decode_dimension: None,
+            gone_after_ms: GONE_AFTER_MS,
+            cooldown_ms: 3000,
+            confirm_frames: 1,
+            confirm_window: 1,
+            charset: None,
+            backend: Backend::Zxing,
+            decode_threads: 1,
+            decode_interval_ms: 51,
+        }
+    }
+}
+
+/// Called from the decode thread with codes that have newly come into view, see `Decoder::on_result`.
+pub type ResultCallback = Box<dyn Fn(&[QRCode]) + Send + Sync>;
+
+/// A code found by the camera's own detector rather than zxing-cpp, see `Backend::Native`.
+#[derive(Clone, Debug)]
+pub struct Detection {
+    pub text: String,
+    pub format: CodeFormat,
+    /// Corners (top left, top right, bottom right, bottom left) as fractions of the frame size.
+    pub corners: [[f64; 2]; 4],
+}
+
+/// Where a code fits in a Structured Append sequence, see `Sequences`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SequencePart {
+    /// Position in the sequence, starting at 0.
+    pub index: usize,
+    /// How many codes are in the sequence.
+    pub size: usize,
+    /// Identifies the sequence, for QR codes the parity of the whole payload.
+    pub id: String,
+}
+
+#[derive(Clone, Debug)]
 pub struct QRCode {
     text: String,
+    bytes: Vec<u8>,
+    format: BarcodeFormat,
     position: Position,
+    frame_size: (u32, u32),
+    timestamp: Option<f64>,
+    sequence: Option<SequencePart>,
+}
+
+impl QRCode {
+    pub fn from_barcode(barcode: &Barcode, frame_size: (u32, u32)) -> Self {
+        let sequence_size = barcode.sequence_size();
+        Self {
+            text: barcode.text(),
+            bytes: barcode.bytes(),
+            format: barcode.format(),
+            position: barcode.position(),
+            frame_size,
+            timestamp: None,
+            sequence: (sequence_size > 1).then(|| SequencePart {
+                index: barcode.sequence_index().max(0) as usize,
+                size: sequence_size as usize,
+                id: barcode.sequence_id(),
+            }),
+        }
+    }
+
+    /// Join the parts of a Structured Append sequence, in order, into one code positioned where the
+    /// last part was. `None` if there are no parts.
+    pub(crate) fn combine(parts: &[QRCode]) -> Option<Self> {
+        let last = parts.last()?;
+        Some(Self {
+            text: parts.iter().map(|part| part.text.as_str()).collect(),
+            bytes: parts.iter().flat_map(|part| part.bytes.iter().copied()).collect(),
+            sequence: None,
+            ..last.clone()
+        })
+    }
+
+    /// A code found by the camera's own detector, positioned in a frame of `frame_size`.
+    pub fn from_detection(detection: &Detection, frame_size: (u32, u32)) -> Self {
+        let [top_left, top_right, bottom_right, bottom_left] = detection.corners.map(|[x, y]| PointI {
+            x: (x * frame_size.0 as f64).round() as i32,
+            y: (y * frame_size.1 as f64).round() as i32,
+        });
+        Self {
+            text: detection.text.clone(),
+            bytes: detection.text.as_bytes().to_vec(),
+            format: detection.format.barcode_format(),
+            position: Position {
+                top_left,
+                top_right,
+                bottom_right,
+                bottom_left,
+            },
+            frame_size,
+            timestamp: None,
+            sequence: None,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The raw payload before it was decoded as text.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Decode the text from the raw payload in `encoding`, overriding zxing-cpp's choice.
+    pub(crate) fn with_charset(mut self, encoding: &'static Encoding) -> Self {
+        self.text = encoding.decode_without_bom_handling(&self.bytes).0.into_owned();
+        self
+    }
+
+    pub fn format(&self) -> BarcodeFormat {
+        self.format
+    }
+
+    /// Where the code fits in a Structured Append sequence, `None` for a code on its own.
+    pub fn sequence(&self) -> Option<&SequencePart> {
+        self.sequence.as_ref()
+    }
+
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    /// Move the corners of the code, e.g. to undo a transform applied to the frame before decoding.
+    pub(crate) fn map_position(mut self, f: impl Fn(PointI) -> PointI) -> Self {
+        self.position.top_left = f(self.position.top_left);
+        self.position.top_right = f(self.position.top_right);
+        self.position.bottom_right = f(self.position.bottom_right);
+        self.position.bottom_left = f(self.position.bottom_left);
+        self
+    }
+
+    /// Corners (top left, top right, bottom right, bottom left) as `[x, y]` pixels.
+    pub fn corners(&self) -> [[i32; 2]; 4] {
+        [
+            self.position.top_left,
+            self.position.top_right,
+            self.position.bottom_right,
+            self.position.bottom_left,
+        ]
+        .map(|point| [point.x, point.y])
+    }
+
+    /// Area of the code's quadrilateral in square pixels.
+    pub fn area(&self) -> f64 {
+        let corners = self.corners();
+        let twice_area: i64 = (0..4)
+            .map(|i| {
+                let ([x0, y0], [x1, y1]) = (corners[i], corners[(i + 1) % 4]);
+                x0 as i64 * y1 as i64 - x1 as i64 * y0 as i64
+            })
+            .sum();
+        twice_area.abs() as f64 / 2.
+    }
+
+    /// Approximate side length in pixels, from the mean length of the diagonals, which holds up better
+    /// than the edges when the code is seen at an angle.
+    pub fn side(&self) -> f64 {
+        let [top_left, top_right, bottom_right, bottom_left] = self.corners();
+        let length = |[x0, y0]: [i32; 2], [x1, y1]: [i32; 2]| ((x1 - x0) as f64).hypot((y1 - y0) as f64);
+        (length(top_left, bottom_right) + length(top_right, bottom_left)) / 2. / std::f64::consts::SQRT_2
+    }
+
+    /// Corners as fractions (0.0 to 1.0) of the frame width and height, for consumers that
+    /// don't know the frame dimensions.
+    pub fn normalized_corners(&self) -> [[f64; 2]; 4] {
+        normalize_corners(self.corners(), self.frame_size)
+    }
+
+    /// Scale the position to a resized copy of the frame it was decoded from, e.g. to undo
+    /// downscaling before decoding.
+    pub(crate) fn rescale(self, frame_size: (u32, u32)) -> Self {
+        let scale_x = frame_size.0 as f64 / self.frame_size.0.max(1) as f64;
+        let scale_y = frame_size.1 as f64 / self.frame_size.1.max(1) as f64;
+        let mut qrcode = self.map_position(|point| PointI {
+            x: (point.x as f64 * scale_x).round() as i32,
+            y: (point.y as f64 * scale_y).round() as i32,
+        });
+        qrcode.frame_size = frame_size;
+        qrcode
+    }
+
+    /// Move a code decoded from a crop of a frame into the coordinates of the whole frame, given the
+    /// crop's offset and the whole frame's size.
+    pub(crate) fn translate(self, (dx, dy): (i32, i32), frame_size: (u32, u32)) -> Self {
+        let mut qrcode = self.map_position(|point| PointI {
+            x: point.x + dx,
+            y: point.y + dy,
+        });
+        qrcode.frame_size = frame_size;
+        qrcode
+    }
+
+    pub(crate) fn with_timestamp(mut self, timestamp: Option<f64>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Presentation time in seconds of the camera frame the code was decoded from, on the capture
+    /// device's clock, `None` for codes decoded from files.
+    pub fn timestamp(&self) -> Option<f64> {
+        self.timestamp
+    }
+
+    /// Dimensions of the frame the code was decoded from, which `position` is relative to.
+    pub fn frame_size(&self) -> (u32, u32) {
+        self.frame_size
+    }
 }
 
 impl fmt::Display for QRCode {
@@ -19,37 +387,328 @@ impl fmt::Display for QRCode {
         write!(
             f,
             "{} at {}/{}",
-            self.text, self.position.top_left, self.position.bottom_right
+            truncate_for_display(&self.text, MAX_DISPLAY_CHARS),
+            self.position.top_left,
+            self.position.bottom_right
         )
     }
 }
 
-impl Into<QRCode> for &Barcode {
-    fn into(self) -> QRCode {
-        QRCode {
-            text: self.text(),
-            position: self.position(),
+pub fn normalize_corners(corners: [[i32; 2]; 4], (width, height): (u32, u32)) -> [[f64; 2]; 4] {
+    corners.map(|[x, y]| [x as f64 / width.max(1) as f64, y as f64 / height.max(1) as f64])
+}
+
+/// Collapse codes with the same text found more than once in a frame, which zxing-cpp sometimes
+/// reports at slightly different positions, into the one with the largest area.
+///
+/// This is distinct from `Dedup`, which tracks codes across frames.
+pub fn collapse_duplicates(qrcodes: Vec<QRCode>) -> Vec<QRCode> {
+    let mut collapsed: Vec<QRCode> = Vec::with_capacity(qrcodes.len());
+    for qrcode in qrcodes {
+        match collapsed.iter_mut().find(|existing| existing.text == qrcode.text) {
+            Some(existing) if qrcode.area() > existing.area() => *existing = qrcode,
+            Some(_) => {}
+            None => collapsed.push(qrcode),
         }
     }
+    collapsed
+}
+
+pub fn barcode_reader(options: &DecodeOptions) -> BarcodeReader {
+    let formats = options
+        .formats
+        .iter()
+        .fold(BarcodeFormats::default(), |formats, format| {
+            formats | format.barcode_format()
+        });
+    zxingcpp::read()
+        .formats(formats)
+        .try_invert(options.try_invert)
+        .try_harder(options.try_harder)
+        .max_number_of_symbols(options.max_results as i32)
+}
+
+/// Decode all codes in an image, applying any fallbacks enabled in `options`.
+///
+/// With more than one decode thread each thread uses its own reader rather than `reader`.
+pub fn decode_image(
+    reader: &BarcodeReader,
+    grey_img: &GrayImage,
+    options: &DecodeOptions,
+) -> Result<Vec<QRCode>, zxingcpp::Error> {
+    let frame_size = grey_img.dimensions();
+    if frame_size.0 == 0 || frame_size.1 == 0 {
+        return Ok(Vec::new());
+    }
+    let longest = frame_size.0.max(frame_size.1);
+    if let Some(max) = options.max_decode_dimension.filter(|&max| max > 0 && longest > max) {
+        let scale = max as f64 / longest as f64;
+        let width = ((frame_size.0 as f64 * scale).round() as u32).max(1);
+        let height = ((frame_size.1 as f64 * scale).round() as u32).max(1);
+        let small = resize(grey_img, width, height, FilterType::Triangle);
+        let options = DecodeOptions {
+            max_decode_dimension: None,
+            ..options.clone()
+        };
+        return Ok(decode_image(reader, &small, &options)?
+            .into_iter()
+            .map(|qrcode| qrcode.rescale(frame_size))
+            .collect());
+    }
+    if options.normalize_contrast {
+        let options = DecodeOptions {
+            normalize_contrast: false,
+            ..options.clone()
+        };
+        return decode_image(reader, &normalize_contrast(grey_img), &options);
+    }
+    if options.decode_threads > 1 {
+        return decode_tiled(grey_img, options);
+    }
+    let qrcodes: Vec<QRCode> = reader
+        .from(grey_img)?
+        .iter()
+        .map(|barcode| QRCode::from_barcode(barcode, frame_size))
+        .collect();
+    let qrcodes = if qrcodes.is_empty() && options.deskew {
+        decode_normalized(reader, grey_img)?
+    } else {
+        qrcodes
+    };
+    let charset = options
+        .charset
+        .as_deref()
+        .and_then(|label| Encoding::for_label(label.as_bytes()));
+    Ok(match charset {
+        Some(encoding) => qrcodes
+            .into_iter()
+            .map(|qrcode| qrcode.with_charset(encoding))
+            .collect(),
+        None => qrcodes,
+    })
+}
+
+/// Stretch the darkest and brightest percent of pixels out to black and white, ignoring a few outliers
+/// (e.g. specular highlights) so they don't stop the stretch.
+pub fn normalize_contrast(grey_img: &GrayImage) -> GrayImage {
+    let (lower, upper) = (percentile(grey_img, 1), percentile(grey_img, 99));
+    if upper <= lower {
+        return grey_img.clone();
+    }
+    stretch_contrast(grey_img, lower, upper, 0, 255)
+}
+
+/// The state a `Decoder` shares with its decode thread, built once in `Decoder::new` and cloned into
+/// each decode thread it starts.
+#[derive(Clone)]
+pub struct DecodeShared {
+    /// The newest frame waiting to be decoded, and its presentation time.
+    pub grey_image: Arc<Mutex<Option<(GrayImage, Option<f64>)>>>,
+    /// The most recent frame the decode thread has finished with, kept for re-decoding.
+    pub last_grey_image: Arc<Mutex<Option<GrayImage>>>,
+    /// Codes from the camera's own detector, used in place of zxing-cpp with `Backend::Native`.
+    pub detections: Arc<Mutex<Option<Vec<Detection>>>>,
+    pub qrcodes: Arc<Mutex<Option<Vec<QRCode>>>>,
+    /// Codes in the most recently decoded frame, left in place unlike `qrcodes`.
+    pub latest_qrcodes: Arc<Mutex<Vec<QRCode>>>,
+    pub new_qrcodes: Arc<Mutex<Vec<QRCode>>>,
+    pub sequences: Sequences,
+    pub stats: DecodeStats,
+    pub callbacks: Arc<Mutex<Vec<ResultCallback>>>,
+    pub options: Arc<Mutex<DecodeOptions>>,
+    pub stop: Arc<AtomicBool>,
+    /// Frames are still converted for the preview, but the decode thread drops them unseen.
+    pub decode_paused: Arc<AtomicBool>,
 }
 
-pub fn decode_qr(
-    grey_img_mutex: Arc<Mutex<Option<GrayImage>>>,
-    qrcodes: Arc<Mutex<Option<Vec<QRCode>>>>,
-    stop: Arc<AtomicBool>,
-) {
-    let barcode_reader = zxingcpp::read().formats(BarcodeFormat::QRCode).try_invert(false);
+pub fn decode_qr(shared: DecodeShared) {
+    let DecodeShared {
+        grey_image: grey_img_mutex,
+        last_grey_image: last_grey_img,
+        detections,
+        qrcodes,
+        latest_qrcodes,
+        new_qrcodes,
+        sequences,
+        stats,
+        callbacks,
+        options,
+        stop,
+        decode_paused: paused,
+    } = shared;
+    let mut current_options = lock(&options).clone();
+    let mut barcode_reader = barcode_reader(&current_options);
+    let mut dedup = Dedup::new(Duration::from_millis(current_options.cooldown_ms));
+    dedup.set_gone_after(Duration::from_millis(current_options.gone_after_ms));
+    dedup.set_confirmation(current_options.confirm_frames, current_options.confirm_window);
     loop {
-        std::thread::sleep(std::time::Duration::from_millis(51));
-        let grey_img_opt = { grey_img_mutex.lock().ok().and_then(|mut img| img.take()) };
-        if let Some(grey_img) = grey_img_opt {
-            let barcodes = barcode_reader.from(&grey_img).unwrap();
-            if let Ok(mut qrcodes) = qrcodes.lock() {
-                *qrcodes = Some(barcodes.iter().map(Into::into).collect());
-            }
-        }
+        std::thread::sleep(Duration::from_millis(current_options.decode_interval_ms));
+        // check before decoding so shutdown doesn't wait on a slow decode of a frame nobody will see
         if stop.load(Ordering::Relaxed) {
             break;
         }
+        let latest_options = lock(&options).clone();
+        if latest_options != current_options {
+            barcode_reader = barcode_reader(&latest_options);
+            dedup.set_cooldown(Duration::from_millis(latest_options.cooldown_ms));
+            dedup.set_gone_after(Duration::from_millis(latest_options.gone_after_ms));
+            dedup.set_confirmation(latest_options.confirm_frames, latest_options.confirm_window);
+            current_options = latest_options;
+        }
+        if paused.load(Ordering::Relaxed) {
+            // drop frames rather than decoding them late on resume, and clear the codes so none are
+            // left boxed that may have since moved or gone
+            if lock(&grey_img_mutex).take().is_some() {
+                lock(&latest_qrcodes).clear();
+                *lock(&qrcodes) = Some(Vec::new());
+            }
+            continue;
+        }
+        let grey_img_opt = lock(&grey_img_mutex).take();
+        if let Some((grey_img, timestamp)) = grey_img_opt {
+            let started = Instant::now();
+            let result = match current_options.backend {
+                Backend::Zxing => decode_image(&barcode_reader, &grey_img, &current_options),
+                // the camera's detector runs alongside capture, the frame is only needed for its size, and
+                // it only reports while codes are in view so no detections means none in this frame
+                Backend::Native => Ok(lock(&detections)
+                    .take()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|detection| QRCode::from_detection(detection, grey_img.dimensions()))
+                    .collect()),
+            };
+            *lock(&last_grey_img) = Some(grey_img);
+            let decoded: Vec<QRCode> = match result {
+                Ok(decoded) => {
+                    let decoded = collapse_duplicates(decoded);
+                    stats.record(started.elapsed(), &decoded);
+                    decoded
+                        .into_iter()
+                        .map(|qrcode| qrcode.with_timestamp(timestamp))
+                        .collect()
+                }
+                Err(err) => {
+                    log::warn!("failed to decode frame: {}", err);
+                    continue;
+                }
+            };
+            let new_decoded = sequences.assemble(dedup.update(&decoded));
+            if !new_decoded.is_empty() {
+                for callback in lock(&callbacks).iter() {
+                    callback(&new_decoded);
+                }
+            }
+            lock(&new_qrcodes).extend(new_decoded);
+            *lock(&latest_qrcodes) = decoded.clone();
+            *lock(&qrcodes) = Some(decoded);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detected(text: &str, size: f64) -> QRCode {
+        let detection = Detection {
+            text: text.to_string(),
+            format: CodeFormat::QrCode,
+            corners: [[0., 0.], [size, 0.], [size, size], [0., size]],
+        };
+        QRCode::from_detection(&detection, (100, 100))
+    }
+
+    #[test]
+    fn collapse_duplicates_keeps_largest() {
+        let collapsed = collapse_duplicates(vec![
+            detected("a", 0.2),
+            detected("b", 0.1),
+            detected("a", 0.5),
+            detected("a", 0.3),
+        ]);
+        let summary: Vec<(&str, f64)> = collapsed.iter().map(|qrcode| (qrcode.text(), qrcode.area())).collect();
+        assert_eq!(summary, vec![("a", 2500.), ("b", 100.)]);
+    }
+
+    #[test]
+    fn collapse_duplicates_keeps_distinct() {
+        let collapsed = collapse_duplicates(vec![detected("a", 0.2), detected("b", 0.2), detected("c", 0.2)]);
+        let texts: Vec<&str> = collapsed.iter().map(QRCode::text).collect();
+        assert_eq!(texts, vec!["a", "b", "c"]);
+    }
+
+    fn decode_texts(grey_img: &GrayImage, options: &DecodeOptions) -> Vec<String> {
+        decode_image(&barcode_reader(options), grey_img, options)
+            .unwrap()
+            .iter()
+            .map(|qrcode| qrcode.text().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn decode_inverted() {
+        let mut inverted = crate::generate::generate("light on dark", 200).unwrap();
+        image::imageops::invert(&mut inverted);
+
+        assert_eq!(
+            decode_texts(&inverted, &DecodeOptions::default()),
+            vec!["light on dark"]
+        );
+        let options = DecodeOptions {
+            try_invert: false,
+            ..DecodeOptions::default()
+        };
+        assert!(decode_texts(&inverted, &options).is_empty());
+    }
+
+    /// Generated codes with dark and light modules only a few levels apart, too little for zxing-cpp's
+    /// binarizer to tell apart unless the contrast is stretched first.
+    fn low_contrast_codes() -> Vec<GrayImage> {
+        (0..6u8)
+            .map(|i| {
+                let mut code = crate::generate::generate(&format!("low contrast {}", i), 200).unwrap();
+                let (dark, light) = (90 + i * 20, 100 + i * 21);
+                for pixel in code.pixels_mut() {
+                    pixel[0] = if pixel[0] < 128 { dark } else { light };
+                }
+                code
+            })
+            .collect()
+    }
+
+    #[test]
+    fn normalize_contrast_decodes_more() {
+        let frames = low_contrast_codes();
+        let hits = |normalize_contrast| {
+            let options = DecodeOptions {
+                normalize_contrast,
+                ..DecodeOptions::default()
+            };
+            frames
+                .iter()
+                .filter(|frame| !decode_texts(frame, &options).is_empty())
+                .count()
+        };
+        let (plain, normalized) = (hits(false), hits(true));
+        assert!(normalized > plain, "{} normalized vs {} plain", normalized, plain);
+    }
+
+    #[test]
+    fn decode_charset_shift_jis() {
+        let text = "こんにちは、世界";
+        let (bytes, _, unmappable) = encoding_rs::SHIFT_JIS.encode(text);
+        assert!(!unmappable);
+        let code = qrcode::QrCode::new(&bytes).unwrap();
+        let grey_img = code.render::<image::Luma<u8>>().min_dimensions(200, 200).build();
+
+        let options = DecodeOptions {
+            charset: Some("shift_jis".to_string()),
+            ..DecodeOptions::default()
+        };
+        let qrcodes = decode_image(&barcode_reader(&options), &grey_img, &options).unwrap();
+        assert_eq!(qrcodes.len(), 1);
+        assert_eq!(qrcodes[0].bytes(), &bytes[..]);
+        assert_eq!(qrcodes[0].text(), text);
     }
 }
@@ -0,0 +1,30 @@
+/// Longest decoded text shown on screen before it's truncated, the full value is kept for copying.
+pub const MAX_DISPLAY_CHARS: usize = 200;
+
+/// Escape control characters (including the ESC that starts ANSI sequences) so decoded text
+/// can't move the cursor, recolour or otherwise take over a terminal it's printed to.
+pub fn escape_control(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_control() {
+            escaped.extend(c.escape_debug());
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Wrap text in single quotes for a POSIX shell, so it's passed as one literal word whatever it contains.
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Escape control characters and truncate to `max_chars`, ending with an ellipsis if anything was cut.
+pub fn truncate_for_display(text: &str, max_chars: usize) -> String {
+    let escaped = escape_control(text);
+    match escaped.char_indices().nth(max_chars) {
+        Some((index, _)) => format!("{}…", &escaped[..index]),
+        None => escaped,
+    }
+}
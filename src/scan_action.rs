@@ -0,0 +1,61 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::qr::QRCode;
+use crate::sanitize::escape_control;
+
+/// One step of what happens when a new code is scanned, configured as an ordered list in the settings
+/// file's `on_scan`, e.g. `[{"action": "copy"}, {"action": "run_command", "command": "say {}"}]`.
+///
+/// Steps run in order for each newly scanned code, so a code held in front of the camera runs them once
+/// and has to leave the frame (and its cooldown pass) before it runs them again.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ScanAction {
+    /// Copy the code's text to the clipboard.
+    Copy,
+    /// Open the code's text in the default browser, only if it's an http or https URL.
+    OpenUrl,
+    /// Show a desktop notification.
+    Notify,
+    /// Play the system alert sound.
+    Beep,
+    /// Run `command` with `sh -c`, with `{}` replaced by the code's text quoted for the shell, the same as
+    /// `--on-scan`.
+    RunCommand { command: String },
+    /// Append the code's text as a line to the file at `path`, creating it if needed. Control characters
+    /// are escaped so each code is exactly one line.
+    AppendToFile { path: PathBuf },
+}
+
+impl ScanAction {
+    /// Check the action's parameters, for rejecting it when settings are loaded rather than on each scan.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::RunCommand { command } if command.trim().is_empty() => Err("run_command has an empty command".into()),
+            Self::AppendToFile { path } if path.as_os_str().is_empty() => {
+                Err("append_to_file has an empty path".into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether text is a URL `ScanAction::OpenUrl` will open, other schemes could launch arbitrary apps.
+pub fn is_web_url(text: &str) -> bool {
+    let lower = text.trim_start().to_ascii_lowercase();
+    lower.starts_with("https://") || lower.starts_with("http://")
+}
+
+pub fn append_to_file(path: &Path, qrcode: &QRCode) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    writeln!(file, "{}", escape_control(qrcode.text())).with_context(|| format!("failed to write {}", path.display()))
+}
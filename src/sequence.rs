@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::decode::lock;
+use crate::qr::QRCode;
+
+/// How long an incomplete sequence is kept without a new part before it's forgotten.
+const SEQUENCE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Partial {
+    parts: Vec<Option<QRCode>>,
+    updated: Instant,
+}
+
+impl Partial {
+    fn new(size: usize) -> Self {
+        Self {
+            parts: vec![None; size],
+            updated: Instant::now(),
+        }
+    }
+}
+
+/// Collects the parts of Structured Append sequences, where a payload is split across several QR codes,
+/// until every part has been scanned.
+///
+/// Parts are matched by their sequence ID (the parity of the whole payload), and may be scanned in any
+/// order. A part scanned twice replaces the earlier copy, and a sequence with no new part for a minute
+/// is forgotten.
+#[derive(Clone, Debug, Default)]
+pub struct Sequences {
+    partials: Arc<Mutex<HashMap<String, Partial>>>,
+}
+
+impl Sequences {
+    /// Hold back parts of sequences from newly scanned codes, adding the combined code in their place
+    /// once the last part of a sequence has been scanned.
+    pub fn assemble(&self, qrcodes: Vec<QRCode>) -> Vec<QRCode> {
+        let mut partials = lock(&self.partials);
+        partials.retain(|_, partial| partial.updated.elapsed() < SEQUENCE_TIMEOUT);
+        let mut assembled = Vec::new();
+        for qrcode in qrcodes {
+            let Some(part) = qrcode.sequence().cloned() else {
+                assembled.push(qrcode);
+                continue;
+            };
+            if part.index >= part.size {
+                log::warn!("ignoring part {} of a sequence of {}", part.index, part.size);
+                continue;
+            }
+            let partial = partials
+                .entry(part.id.clone())
+                .or_insert_with(|| Partial::new(part.size));
+            // a different sequence that happens to have the same parity
+            if partial.parts.len() != part.size {
+                *partial = Partial::new(part.size);
+            }
+            partial.updated = Instant::now();
+            partial.parts[part.index] = Some(qrcode);
+            if partial.parts.iter().all(Option::is_some) {
+                let parts: Vec<QRCode> = partials
+                    .remove(&part.id)
+                    .into_iter()
+                    .flat_map(|partial| partial.parts.into_iter().flatten())
+                    .collect();
+                assembled.extend(QRCode::combine(&parts));
+            }
+        }
+        assembled
+    }
+
+    /// Incomplete sequences as (parts scanned, total parts).
+    pub fn progress(&self) -> Vec<(usize, usize)> {
+        let mut progress: Vec<_> = lock(&self.partials)
+            .values()
+            .map(|partial| (partial.parts.iter().flatten().count(), partial.parts.len()))
+            .collect();
+        progress.sort();
+        progress
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::CaptureOptions;
+use crate::decode::{Mirror, Rotation};
+use crate::extract::Extractor;
+use crate::overlay::{Calibration, OverlayStyle, PreviewFit};
+use crate::product::ProductLookup;
+use crate::qr::DecodeOptions;
+use crate::scan_action::ScanAction;
+
+/// User preferences persisted between runs as JSON.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub rotation: Rotation,
+    pub flip_vertical: bool,
+    pub mirror: Mirror,
+    /// Show a grey preview made from the frame's luma, skipping colour conversion to save CPU.
+    pub grayscale_only: bool,
+    pub notify: bool,
+    /// Briefly flash the preview green when a new code is scanned, for when a sound can't be heard.
+    pub flash_on_scan: bool,
+    /// Whether the preview fills the window, cropping the frame, or shows the whole frame letterboxed.
+    pub preview_fit: PreviewFit,
+    /// Show the latest code in large text over the preview.
+    pub presentation: bool,
+    /// Show a QR code regenerated from the latest code's text beside the preview.
+    pub split_view: bool,
+    /// Show each code regenerated from its text beside its result, with a check if the regenerated
+    /// code decodes to identical bytes, for auditing whether payloads are cleanly representable.
+    pub verify_round_trip: bool,
+    /// Only box and list the most recently scanned code, rather than every code in frame.
+    /// Pairs well with `presentation` for an uncluttered demo display.
+    pub newest_only: bool,
+    /// Show decoded text larger and bold, black on a solid white panel.
+    pub high_contrast: bool,
+    /// Keep the window above other apps' windows, except in fullscreen.
+    pub always_on_top: bool,
+    /// Stop the camera after this many seconds without a code in frame or any key press or click,
+    /// restarting it on the next one. Never stops if unset.
+    pub idle_timeout_secs: Option<u64>,
+    /// Steps run in order for each newly scanned code, see `ScanAction`.
+    pub on_scan: Vec<ScanAction>,
+    /// Regex with named capture groups, e.g. `ORDER:(?<order>\d+)`, whose groups are shown and
+    /// exported in place of the raw text of codes it matches.
+    pub extract: Option<String>,
+    /// Where cmd-s saves frames, `~/Pictures/qrcam` if unset.
+    pub frames_dir: Option<PathBuf>,
+    /// Milliseconds between the window checking for new frames and codes, 37 if unset.
+    pub ui_interval_ms: Option<u64>,
+    pub decode: DecodeOptions,
+    pub product_lookup: ProductLookup,
+    pub capture: CaptureOptions,
+    pub overlay: OverlayStyle,
+    pub calibration: Calibration,
+}
+
+impl Settings {
+    /// Load settings, falling back to defaults if the file is missing or invalid.
+    pub fn load() -> Self {
+        let Some(path) = settings_path() else {
+            return Self::default();
+        };
+        let mut settings: Self = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|err| {
+                log::warn!("ignoring invalid settings file {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        settings.on_scan.retain(|action| match action.validate() {
+            Ok(()) => true,
+            Err(err) => {
+                log::warn!("ignoring on_scan action in {}: {}", path.display(), err);
+                false
+            }
+        });
+        if let Some(pattern) = &settings.extract {
+            if let Err(err) = Extractor::new(pattern) {
+                log::warn!("ignoring extract pattern in {}: {}", path.display(), err);
+                settings.extract = None;
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = settings_path().context("unable to determine settings path")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+fn settings_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join("Library/Application Support/qrcam/settings.json"))
+}
@@ -0,0 +1,81 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use image::GrayImage;
+use serde::Serialize;
+
+use crate::history::HistoryEntry;
+use crate::qr::QRCode;
+
+/// Keyword of the PNG `iTXt` chunk holding a saved frame's `FrameMetadata` as JSON.
+pub const METADATA_KEYWORD: &str = "qrcam";
+
+/// What was decoded from a saved frame, so the file says which codes it contains.
+#[derive(Debug, Serialize)]
+pub struct FrameMetadata {
+    /// Seconds since the unix epoch.
+    pub saved_at: f64,
+    /// Codes decoded from the frame, positioned in its pixels.
+    pub codes: Vec<HistoryEntry>,
+}
+
+impl FrameMetadata {
+    pub fn new(qrcodes: &[QRCode]) -> Self {
+        Self {
+            saved_at: unix_time(),
+            codes: qrcodes.iter().map(HistoryEntry::new).collect(),
+        }
+    }
+}
+
+/// Where frames are saved unless configured otherwise, `~/Pictures/qrcam`.
+pub fn default_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join("Pictures/qrcam"))
+}
+
+/// Save a frame to a new PNG in `dir` with the codes decoded from it embedded, returning its path.
+pub fn save_frame_in(dir: &Path, frame: &GrayImage, qrcodes: &[QRCode]) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let path = dir.join(format!("frame-{:.0}.png", unix_time() * 1000.0));
+    save_frame(&path, frame, qrcodes)?;
+    Ok(path)
+}
+
+/// Save a frame with the codes decoded from it. PNGs get the metadata in an `iTXt` chunk, other formats
+/// can't hold it so it's written beside the image with a `.json` extension.
+pub fn save_frame(path: &Path, frame: &GrayImage, qrcodes: &[QRCode]) -> Result<()> {
+    let metadata = serde_json::to_string(&FrameMetadata::new(qrcodes))?;
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+        write_png(path, frame, metadata).with_context(|| format!("failed to write {}", path.display()))
+    } else {
+        frame
+            .save(path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        let sidecar = path.with_extension("json");
+        fs::write(&sidecar, metadata).with_context(|| format!("failed to write {}", sidecar.display()))
+    }
+}
+
+fn write_png(path: &Path, frame: &GrayImage, metadata: String) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, frame.width(), frame.height());
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    // iTXt rather than tEXt since decoded text is UTF-8
+    encoder.add_itxt_chunk(METADATA_KEYWORD.to_string(), metadata)?;
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(frame.as_raw())?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs_f64())
+        .unwrap_or_default()
+}
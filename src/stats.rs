@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::decode::lock;
+use crate::qr::QRCode;
+
+/// How long decoding frames takes and how often each format is found, for seeing which enabled formats
+/// are worth their cost.
+#[derive(Clone, Debug, Default)]
+pub struct DecodeStats {
+    totals: Arc<Mutex<Totals>>,
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    frames: u64,
+    total: Duration,
+    min: Option<Duration>,
+    max: Option<Duration>,
+    formats: BTreeMap<String, u64>,
+}
+
+/// A snapshot of `DecodeStats`, times are in milliseconds and `None` before the first frame.
+#[derive(Clone, Debug, Serialize)]
+pub struct StatsSummary {
+    pub frames: u64,
+    pub min_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    /// Codes found of each format, counted in every frame they're found in.
+    pub formats: BTreeMap<String, u64>,
+}
+
+impl DecodeStats {
+    /// Record a frame that took `elapsed` to decode and the codes found in it.
+    pub fn record(&self, elapsed: Duration, qrcodes: &[QRCode]) {
+        let mut totals = lock(&self.totals);
+        totals.frames += 1;
+        totals.total += elapsed;
+        totals.min = Some(totals.min.map_or(elapsed, |min| min.min(elapsed)));
+        totals.max = Some(totals.max.map_or(elapsed, |max| max.max(elapsed)));
+        for qrcode in qrcodes {
+            *totals.formats.entry(qrcode.format().to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn reset(&self) {
+        *lock(&self.totals) = Totals::default();
+    }
+
+    pub fn summary(&self) -> StatsSummary {
+        let totals = lock(&self.totals);
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        StatsSummary {
+            frames: totals.frames,
+            min_ms: totals.min.map(ms),
+            avg_ms: (totals.frames > 0).then(|| ms(totals.total) / totals.frames as f64),
+            max_ms: totals.max.map(ms),
+            formats: totals.formats.clone(),
+        }
+    }
+}
+
+impl fmt::Display for StatsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.min_ms, self.avg_ms, self.max_ms) {
+            (Some(min), Some(avg), Some(max)) => write!(
+                f,
+                "decode {:.1}ms avg ({:.1}-{:.1}ms) over {} frames",
+                avg, min, max, self.frames
+            )?,
+            _ => write!(f, "no frames decoded")?,
+        }
+        for (format, count) in &self.formats {
+            write!(f, ", {} {}", format, count)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+
+use crate::decode::lock;
+use crate::history::csv_field;
+use crate::qr::QRCode;
+
+/// How many times each code has come into view, for counting stock by scanning each item in turn.
+///
+/// Counts are fed by new codes from the dedup layer, so a code held in front of the camera counts
+/// once and has to leave the frame (and its cooldown pass) before it counts again.
+#[derive(Clone, Debug, Default)]
+pub struct Tally {
+    counts: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl Tally {
+    pub fn record(&self, qrcode: &QRCode) {
+        *lock(&self.counts).entry(qrcode.text().to_string()).or_default() += 1;
+    }
+
+    pub fn reset(&self) {
+        lock(&self.counts).clear();
+    }
+
+    /// Counts sorted by text.
+    pub fn counts(&self) -> Vec<(String, u32)> {
+        let mut counts: Vec<_> = lock(&self.counts)
+            .iter()
+            .map(|(text, count)| (text.clone(), *count))
+            .collect();
+        counts.sort();
+        counts
+    }
+
+    /// Write the counts to `path`, as a JSON object if it has a `.json` extension, otherwise CSV.
+    pub fn export(&self, path: &Path) -> Result<()> {
+        let counts = self.counts();
+        let content = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => {
+                let counts: serde_json::Map<_, _> =
+                    counts.into_iter().map(|(text, count)| (text, count.into())).collect();
+                serde_json::to_string_pretty(&counts)?
+            }
+            _ => {
+                let mut csv = "text,count\n".to_string();
+                for (text, count) in counts {
+                    csv.push_str(&format!("{},{}\n", csv_field(&text), count));
+                }
+                csv
+            }
+        };
+        fs::write(path, content).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
@@ -0,0 +1,71 @@
+use std::panic;
+use std::thread;
+
+use image::imageops::crop_imm;
+use image::GrayImage;
+
+use crate::qr::{barcode_reader, collapse_duplicates, decode_image, DecodeOptions, QRCode};
+
+/// Split a frame into `options.decode_threads` overlapping bands along its longer side and decode them
+/// concurrently, each with its own reader, for busy frames with many codes.
+///
+/// Bands overlap by a quarter of the frame so most codes straddling a boundary are wholly inside one of
+/// them. Positions are mapped back into the coordinates of the whole frame, and codes found in more
+/// than one band are collapsed.
+pub fn decode_tiled(grey_img: &GrayImage, options: &DecodeOptions) -> Result<Vec<QRCode>, zxingcpp::Error> {
+    let frame_size = grey_img.dimensions();
+    let count = options.decode_threads;
+    let options = DecodeOptions {
+        decode_threads: 1,
+        ..options.clone()
+    };
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = bands(frame_size, count)
+            .into_iter()
+            .map(|(x, y, width, height)| {
+                let options = &options;
+                scope.spawn(move || {
+                    let tile = crop_imm(grey_img, x, y, width, height).to_image();
+                    let qrcodes = decode_image(&barcode_reader(options), &tile, options)?;
+                    Ok::<_, zxingcpp::Error>(
+                        qrcodes
+                            .into_iter()
+                            .map(|qrcode| qrcode.translate((x as i32, y as i32), frame_size))
+                            .collect::<Vec<_>>(),
+                    )
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|panic| panic::resume_unwind(panic)))
+            .collect()
+    });
+
+    let mut qrcodes = Vec::new();
+    for result in results {
+        qrcodes.extend(result?);
+    }
+    let mut qrcodes = collapse_duplicates(qrcodes);
+    qrcodes.truncate(options.max_results.max(1) as usize);
+    Ok(qrcodes)
+}
+
+/// `count` bands as (x, y, width, height), overlapping by a quarter of the frame's longer side.
+fn bands((width, height): (u32, u32), count: u32) -> Vec<(u32, u32, u32, u32)> {
+    let length = width.max(height);
+    let band = length.div_ceil(count.max(1));
+    let overlap = (length / 4).min(band) / 2;
+    (0..count)
+        .map(|index| {
+            let start = (index * band).saturating_sub(overlap);
+            let end = ((index + 1) * band + overlap).min(length);
+            (start, end)
+        })
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| match width >= height {
+            true => (start, 0, end - start, height),
+            false => (0, start, width, end - start),
+        })
+        .collect()
+}
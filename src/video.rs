@@ -0,0 +1,219 @@
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use av_foundation::media_format::AVMediaTypeVideo;
+use core_foundation::base::TCFType;
+use core_media::sample_buffer::{CMSampleBuffer, CMSampleBufferRef};
+use core_video::pixel_buffer::{
+    kCVPixelBufferPixelFormatTypeKey, kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange, CVPixelBuffer,
+};
+use objc2::{class, msg_send, msg_send_id, rc::Id, runtime::AnyObject};
+use objc2_foundation::{NSArray, NSDictionary, NSError, NSNumber, NSString, NSURL};
+use x_media::media_frame::MediaFrame;
+
+use crate::camera::cm_time_seconds;
+use crate::decode::Decoder;
+
+/// `AVAssetReaderStatusFailed`
+const READER_STATUS_FAILED: isize = 3;
+
+/// A video file to decode in place of the camera.
+#[derive(Clone, Debug)]
+pub struct VideoSource {
+    pub path: PathBuf,
+    /// Start again from the beginning at the end of the file.
+    pub looping: bool,
+    /// Speed relative to the video's own frame timing, e.g. 0.5 for half speed.
+    pub rate: f64,
+}
+
+impl VideoSource {
+    /// The file's name, for display in place of a camera's name.
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.path.display().to_string())
+    }
+}
+
+/// Plays a video file into a `Decoder` on a background thread, stopped when dropped.
+pub struct VideoPlayback {
+    stop: Arc<AtomicBool>,
+    join_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl VideoPlayback {
+    /// Open the video and start playing it, returns an error if the file can't be read.
+    pub fn start(source: &VideoSource, decoder: Decoder) -> Result<Self, String> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (started_tx, started_rx) = mpsc::channel();
+        let source = source.clone();
+        let thread_stop = stop.clone();
+        let join_handle = thread::Builder::new()
+            .name("video".to_string())
+            .spawn(move || play(&source, &decoder, &thread_stop, started_tx))
+            .map_err(|err| format!("Failed to start video thread: {}", err))?;
+        match started_rx.recv() {
+            Ok(Ok(())) => Ok(Self {
+                stop,
+                join_handle: Some(join_handle),
+            }),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err("video thread exited unexpectedly".to_string()),
+        }
+    }
+
+    /// Whether playback has reached the end of the file (never while looping) or failed.
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.as_ref().map_or(true, |handle| handle.is_finished())
+    }
+
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(join_handle) = self.join_handle.take() {
+            let _ = join_handle.join();
+        }
+    }
+}
+
+impl Drop for VideoPlayback {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Feed frames to the decoder at the pace of their timestamps, reporting whether the file opened on
+/// `started`.
+fn play(source: &VideoSource, decoder: &Decoder, stop: &AtomicBool, started: mpsc::Sender<Result<(), String>>) {
+    let mut started = Some(started);
+    loop {
+        let reader = match VideoReader::open(&source.path) {
+            Ok(reader) => reader,
+            Err(err) => {
+                match started.take() {
+                    Some(started) => {
+                        let _ = started.send(Err(err));
+                    }
+                    None => log::error!("failed to reopen video: {}", err),
+                }
+                return;
+            }
+        };
+        if let Some(started) = started.take() {
+            let _ = started.send(Ok(()));
+        }
+
+        let begun = Instant::now();
+        let mut first_timestamp = None;
+        while let Some((frame, timestamp)) = reader.next_frame() {
+            if stop.load(Ordering::Relaxed) {
+                return;
+            }
+            if let Some(timestamp) = timestamp {
+                let first_timestamp = *first_timestamp.get_or_insert(timestamp);
+                let due = Duration::from_secs_f64(((timestamp - first_timestamp) / source.rate).max(0.0));
+                if let Some(wait) = due.checked_sub(begun.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+            // frames are requested as bi-planar 4:2:0, which the decoder recognises by its planes
+            decoder.decode(frame, None, timestamp);
+        }
+        if let Err(err) = reader.error() {
+            log::error!("failed to read video: {}", err);
+            return;
+        }
+        if !source.looping || stop.load(Ordering::Relaxed) {
+            log::info!("video finished");
+            return;
+        }
+        log::info!("video finished, starting again");
+    }
+}
+
+/// Reads decoded frames from the first video track of a file with `AVAssetReader`.
+struct VideoReader {
+    reader: Id<AnyObject>,
+    output: Id<AnyObject>,
+}
+
+impl VideoReader {
+    fn open(path: &Path) -> Result<Self, String> {
+        if !path.is_file() {
+            return Err(format!("{} is not a file", path.display()));
+        }
+        let path = NSString::from_str(&path.to_string_lossy());
+        unsafe {
+            let url = NSURL::fileURLWithPath(&path);
+            let asset: Id<AnyObject> =
+                msg_send_id![class!(AVURLAsset), URLAssetWithURL: &*url, options: ptr::null::<AnyObject>()];
+            let tracks: Id<NSArray<AnyObject>> = msg_send_id![&*asset, tracksWithMediaType: AVMediaTypeVideo];
+            let track = tracks.first().ok_or("no video track")?;
+
+            let reader: Result<Id<AnyObject>, Id<NSError>> =
+                msg_send_id![class!(AVAssetReader), assetReaderWithAsset: &*asset, error: _];
+            let reader = reader.map_err(|err| err.localizedDescription().to_string())?;
+
+            // bi-planar 4:2:0 is what hardware decoders produce, so asking for it avoids a conversion
+            let key = &*(kCVPixelBufferPixelFormatTypeKey as *const NSString);
+            let settings = NSDictionary::from_vec(
+                &[key],
+                vec![NSNumber::new_u32(kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange)],
+            );
+            let output: Id<AnyObject> = msg_send_id![
+                class!(AVAssetReaderTrackOutput),
+                assetReaderTrackOutputWithTrack: track,
+                outputSettings: &*settings
+            ];
+            let _: () = msg_send![&*output, setAlwaysCopiesSampleData: false];
+            let can_add: bool = msg_send![&*reader, canAddOutput: &*output];
+            if !can_add {
+                return Err("cannot read video track".to_string());
+            }
+            let _: () = msg_send![&*reader, addOutput: &*output];
+            let started: bool = msg_send![&*reader, startReading];
+            if !started {
+                return Err(Self { reader, output }
+                    .error()
+                    .err()
+                    .unwrap_or("failed to start reading".to_string()));
+            }
+            Ok(Self { reader, output })
+        }
+    }
+
+    /// The next frame and its timestamp in seconds, `None` at the end of the file or on error.
+    fn next_frame(&self) -> Option<(MediaFrame, Option<f64>)> {
+        loop {
+            let sample_buffer: CMSampleBufferRef = unsafe { msg_send![&*self.output, copyNextSampleBuffer] };
+            if sample_buffer.is_null() {
+                return None;
+            }
+            let sample_buffer = unsafe { CMSampleBuffer::wrap_under_create_rule(sample_buffer) };
+            let frame = sample_buffer
+                .get_image_buffer()
+                .and_then(|image_buffer| image_buffer.downcast::<CVPixelBuffer>())
+                .and_then(|pixel_buffer| MediaFrame::from_pixel_buffer(&pixel_buffer).ok());
+            // buffers without an image, e.g. marking the end of an edit, are skipped
+            if let Some(frame) = frame {
+                return Some((frame, cm_time_seconds(sample_buffer.get_presentation_time_stamp())));
+            }
+        }
+    }
+
+    fn error(&self) -> Result<(), String> {
+        let status: isize = unsafe { msg_send![&*self.reader, status] };
+        if status != READER_STATUS_FAILED {
+            return Ok(());
+        }
+        let error: Option<Id<NSError>> = unsafe { msg_send_id![&*self.reader, error] };
+        Err(error.map_or("unknown error".to_string(), |error| {
+            error.localizedDescription().to_string()
+        }))
+    }
+}
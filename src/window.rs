@@ -0,0 +1,36 @@
+use objc2::{msg_send, msg_send_id, rc::Id, runtime::AnyObject};
+use raw_window_handle::{HasWindowHandle, RawWindowHandle};
+
+// NSNormalWindowLevel and NSFloatingWindowLevel
+const NORMAL_WINDOW_LEVEL: isize = 0;
+const FLOATING_WINDOW_LEVEL: isize = 3;
+
+/// Float the window above other apps' windows, or return it to the normal level.
+pub fn set_always_on_top(window: &impl HasWindowHandle, on_top: bool) {
+    let handle = match window.window_handle() {
+        Ok(handle) => handle,
+        Err(err) => {
+            log::warn!("unable to get window handle: {}", err);
+            return;
+        }
+    };
+    let RawWindowHandle::AppKit(handle) = handle.as_raw() else {
+        log::warn!("always on top is only supported on macOS");
+        return;
+    };
+    let level = if on_top {
+        FLOATING_WINDOW_LEVEL
+    } else {
+        NORMAL_WINDOW_LEVEL
+    };
+    unsafe {
+        let ns_view = handle.ns_view.as_ptr() as *mut AnyObject;
+        let ns_window: Option<Id<AnyObject>> = msg_send_id![&*ns_view, window];
+        match ns_window {
+            Some(ns_window) => {
+                let _: () = msg_send![&*ns_window, setLevel: level];
+            }
+            None => log::warn!("view has no window, not changing its level"),
+        }
+    }
+}